@@ -0,0 +1,278 @@
+//! `malk-lex`: lex a file and dump the token tree, for debugging grammars built on `malk-lexer`.
+
+use malk_lexer::{
+    Token, TokenKind, TokensBuf, LexError, LexerConfig, CommentSyntax, SymbolTable, Lexer,
+    FlatToken, FileId, SymbolTableWarning, analyze_symbol_table,
+};
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+const USAGE: &str = "\
+usage: malk-lex [OPTIONS] [FILE]
+
+Lex FILE, or standard input if no file is given, and print the tokens.
+
+options:
+    -s, --symbol SYM            add SYM to the symbol table
+        --symbols LIST          add each whitespace-separated symbol in LIST
+    -S, --symbols-file PATH     add each whitespace-separated symbol in the file at PATH
+        --longest-match         match the longest symbol rather than requiring an unambiguous
+                                symbol table
+        --line-comment STR      treat STR as the start of a line comment
+        --block-comment OPEN CLOSE
+                                treat OPEN and CLOSE as block comment delimiters
+        --nested-comments       allow block comments to nest
+    -f, --format FORMAT         output format: `tree` (the default), `json` or `lines`
+    -r, --recover               carry on past errors and print the best-effort tokens
+    -h, --help                  print this message
+
+Exits with status 1 if the input couldn't be lexed and 2 on any other error.
+";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Tree,
+    Json,
+    Lines,
+}
+
+struct Options {
+    symbols: Vec<String>,
+    longest_match: bool,
+    line_comments: Vec<String>,
+    block_comments: Vec<(String, String)>,
+    nested_comments: bool,
+    format: Format,
+    recover: bool,
+    path: Option<String>,
+}
+
+/// Print an error message and exit with status 2.
+fn fail(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "malk-lex: {}", message);
+    process::exit(2);
+}
+
+fn read_file(path: &str) -> String {
+    let mut text = String::new();
+    let result = File::open(path).and_then(|mut file| file.read_to_string(&mut text));
+    if let Err(e) = result {
+        fail(&format!("can't read `{}`: {}", path, e));
+    }
+    text
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        symbols: Vec::new(),
+        longest_match: false,
+        line_comments: Vec::new(),
+        block_comments: Vec::new(),
+        nested_comments: false,
+        format: Format::Tree,
+        recover: false,
+        path: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| match args.next() {
+            Some(value) => value,
+            None => fail(&format!("`{}` needs a value", name)),
+        };
+        match &arg[..] {
+            "-s" | "--symbol" => options.symbols.push(value(&arg)),
+            "--symbols" => {
+                options.symbols.extend(value(&arg).split_whitespace().map(String::from));
+            },
+            "-S" | "--symbols-file" => {
+                let text = read_file(&value(&arg));
+                options.symbols.extend(text.split_whitespace().map(String::from));
+            },
+            "--longest-match" => options.longest_match = true,
+            "--line-comment" => options.line_comments.push(value(&arg)),
+            "--block-comment" => {
+                let open = value(&arg);
+                let close = value(&arg);
+                options.block_comments.push((open, close));
+            },
+            "--nested-comments" => options.nested_comments = true,
+            "-f" | "--format" => {
+                options.format = match &value(&arg)[..] {
+                    "tree" => Format::Tree,
+                    "json" => Format::Json,
+                    "lines" => Format::Lines,
+                    format => fail(&format!("unknown format `{}`", format)),
+                };
+            },
+            "-r" | "--recover" => options.recover = true,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            },
+            "-" => options.path = None,
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`, see --help", arg)),
+            _ => {
+                if options.path.is_some() {
+                    fail("more than one input file given");
+                }
+                options.path = Some(arg.clone());
+            },
+        }
+    }
+    options
+}
+
+/// Describe a token's kind and text on one line.
+fn describe<S>(token: &Token<S>) -> String {
+    match token.kind {
+        TokenKind::Ident(s) => format!("Ident {:?}", s),
+        TokenKind::Whitespace(s) => format!("Whitespace {:?}", s),
+        TokenKind::Symbol(s, _) => format!("Symbol {:?}", s),
+        TokenKind::Bracket(open, _, close) => match close {
+            Some(close) => format!("Bracket {:?} {:?}", open, close),
+            None => format!("Bracket {:?} (unclosed)", open),
+        },
+        TokenKind::String(ref string) => format!("String {}", string),
+        TokenKind::Number(ref number) => format!("Number {}", number),
+        TokenKind::Comment(s) => format!("Comment {:?}", s),
+        TokenKind::Error(s) => format!("Error {:?}", s),
+    }
+}
+
+/// Print the tree with each token on its own line, indented by its depth.
+fn print_tree<S>(out: &mut dyn Write, tokens: &TokensBuf<S>) -> io::Result<()> {
+    let mut stack = vec![tokens.tokens.iter()];
+    loop {
+        let token = match stack.last_mut() {
+            Some(iter) => iter.next(),
+            None => return Ok(()),
+        };
+        match token {
+            Some(token) => {
+                let indent = "  ".repeat(stack.len() - 1);
+                writeln!(out, "{}{} {}", indent, token.span(), describe(token))?;
+                if let TokenKind::Bracket(_, ref inner, _) = token.kind {
+                    stack.push(inner.tokens.iter());
+                }
+            },
+            None => {
+                stack.pop();
+            },
+        }
+    }
+}
+
+/// Print each event from the lexer on its own line.
+fn print_lines(out: &mut dyn Write, lexer: Lexer, errors: &mut Vec<LexError>) -> io::Result<()> {
+    for event in lexer {
+        match event {
+            Ok(FlatToken::Token(token)) => {
+                writeln!(out, "{} {}", token.start, describe(&token))?;
+            },
+            Ok(FlatToken::Open(c, span)) => {
+                writeln!(out, "{} Open {:?}", span.start, c)?;
+            },
+            Ok(FlatToken::Close(Some(c), span)) => {
+                writeln!(out, "{} Close {:?}", span.start, c)?;
+            },
+            Ok(FlatToken::Close(None, span)) => {
+                writeln!(out, "{} Close (missing)", span.start)?;
+            },
+            Err(e) => errors.push(e),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = parse_args();
+    let (file_name, src) = match options.path {
+        Some(ref path) => (&path[..], read_file(path)),
+        None => {
+            let mut src = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut src) {
+                fail(&format!("can't read standard input: {}", e));
+            }
+            ("<stdin>", src)
+        },
+    };
+
+    let symbols: Vec<&str> = options.symbols.iter().map(|s| &s[..]).collect();
+    // Unique mode already rejects duplicated and ambiguous symbols, so only unreachable ones are
+    // worth a warning there.
+    for warning in analyze_symbol_table(&symbols[..]) {
+        if options.longest_match || matches!(warning, SymbolTableWarning::Unreachable { .. }) {
+            let _ = writeln!(io::stderr(), "warning: {}", warning);
+        }
+    }
+    let symbol_table = match options.longest_match {
+        true => SymbolTable::longest_match(&symbols[..]),
+        false => SymbolTable::new(&symbols[..]),
+    };
+    let symbol_table = match symbol_table {
+        Ok(symbol_table) => symbol_table,
+        Err(e) => {
+            let _ = write!(io::stderr(), "{}", LexError::InvalidSymbolTable(e).render(file_name, &src));
+            process::exit(2);
+        },
+    };
+    let comments = CommentSyntax {
+        line: options.line_comments.iter().map(|s| &s[..]).collect(),
+        block: options.block_comments.iter().map(|(open, close)| (&open[..], &close[..])).collect(),
+        nested: options.nested_comments,
+    };
+    let config = LexerConfig::new(symbol_table).comments(comments);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut errors = Vec::new();
+    let result = match options.format {
+        Format::Lines => {
+            let lexer = match options.recover {
+                true => Lexer::recovering(FileId::default(), &src, &config),
+                false => Lexer::new(FileId::default(), &src, &config),
+            };
+            print_lines(&mut out, lexer, &mut errors)
+        },
+        format => {
+            let tokens = match options.recover {
+                true => {
+                    let (tokens, recovered) = config.lex_recovering(FileId::default(), &src);
+                    errors = recovered;
+                    Some(tokens)
+                },
+                false => match config.lex(FileId::default(), &src) {
+                    Ok(tokens) => Some(tokens),
+                    Err(e) => {
+                        errors.push(e);
+                        None
+                    },
+                },
+            };
+            match tokens {
+                Some(ref tokens) if format == Format::Json => {
+                    serde_json::to_writer_pretty(&mut out, tokens)
+                        .map_err(io::Error::from)
+                        .and_then(|()| writeln!(out))
+                },
+                Some(ref tokens) => print_tree(&mut out, tokens),
+                None => Ok(()),
+            }
+        },
+    };
+    if let Err(e) = result {
+        fail(&format!("can't write output: {}", e));
+    }
+
+    if !errors.is_empty() {
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        for e in &errors {
+            let _ = writeln!(stderr, "{}", e.render(file_name, &src));
+        }
+        process::exit(1);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::tokens::TokensBuf;
+use crate::symbol_table::SymbolTable;
+use crate::lex::{LexError, lex_with_config, lex_recovering_with_config};
+use crate::relex::{Edit, relex_with_config};
+use crate::source_map::FileId;
+
+use alloc::vec::Vec;
+
+use unicode_brackets::UnicodeBrackets;
+use unicode_xid::UnicodeXID;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// The comment syntax recognized by the lexer. The default recognizes no comments at all.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CommentSyntax<'c> {
+    /// Strings which start a comment running to the end of the line, eg. `//` or `#`.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub line: Vec<&'c str>,
+    /// Pairs of strings which open and close a block comment, eg. `("/*", "*/")`.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub block: Vec<(&'c str, &'c str)>,
+    /// Whether block comments can be nested inside each other.
+    pub nested: bool,
+}
+
+/// Configuration for the lexer. Built from a symbol table and then customized with the builder
+/// methods. The defaults match the behaviour of `lex`. A config can be reused to lex any number
+/// of sources.
+#[derive(Debug, Clone)]
+pub struct LexerConfig<'s, S = usize> {
+    symbol_table: SymbolTable<'s, S>,
+    comments: CommentSyntax<'s>,
+    quotes: Vec<char>,
+    escapes: bool,
+    raw_strings: bool,
+    numbers: bool,
+    ident_start: fn(char) -> bool,
+    ident_continue: fn(char) -> bool,
+    brackets: Option<Vec<(char, char)>>,
+    max_depth: Option<usize>,
+}
+
+/// The default limit on how deeply brackets can be nested.
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
+fn is_xid_start(c: char) -> bool {
+    c.is_xid_start()
+}
+
+fn is_xid_continue(c: char) -> bool {
+    c.is_xid_continue()
+}
+
+impl<'s, S> LexerConfig<'s, S> {
+    /// Create a config which uses the given symbol table.
+    pub fn new(symbol_table: SymbolTable<'s, S>) -> LexerConfig<'s, S> {
+        LexerConfig {
+            symbol_table,
+            comments: CommentSyntax::default(),
+            quotes: vec!['\'', '"'],
+            escapes: true,
+            raw_strings: true,
+            numbers: true,
+            ident_start: is_xid_start,
+            ident_continue: is_xid_continue,
+            brackets: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+        }
+    }
+
+    /// Set the comment syntax. By default there are no comments.
+    pub fn comments(mut self, comments: CommentSyntax<'s>) -> LexerConfig<'s, S> {
+        self.comments = comments;
+        self
+    }
+
+    /// Set the characters which delimit string literals. Defaults to `'` and `"`.
+    pub fn quotes(mut self, quotes: &[char]) -> LexerConfig<'s, S> {
+        self.quotes = quotes.to_vec();
+        self
+    }
+
+    /// Set whether backslash escapes are processed inside string literals. If not, backslashes
+    /// are treated as ordinary characters. Defaults to `true`.
+    pub fn escapes(mut self, escapes: bool) -> LexerConfig<'s, S> {
+        self.escapes = escapes;
+        self
+    }
+
+    /// Set whether raw string literals such as `r"..."` and `r#"..."#` are recognized. Raw
+    /// strings can contain any number of `#` characters in their fences and have no escape
+    /// processing. They are always delimited by `"`, so they're only recognized while `"` is one
+    /// of the `quotes`. Defaults to `true`.
+    pub fn raw_strings(mut self, raw_strings: bool) -> LexerConfig<'s, S> {
+        self.raw_strings = raw_strings;
+        self
+    }
+
+    /// Set whether numeric literals are recognized. If not, digits can only appear in symbols
+    /// and identifiers. Defaults to `true`.
+    pub fn numbers(mut self, numbers: bool) -> LexerConfig<'s, S> {
+        self.numbers = numbers;
+        self
+    }
+
+    /// Set the rules for identifiers. An identifier is a character matching `start` followed by
+    /// any number of characters matching `cont`. Defaults to `XID_Start` and `XID_Continue`.
+    pub fn idents(mut self, start: fn(char) -> bool, cont: fn(char) -> bool) -> LexerConfig<'s, S> {
+        self.ident_start = start;
+        self.ident_continue = cont;
+        self
+    }
+
+    /// Set the pairs of opening and closing characters which are treated as brackets. Defaults
+    /// to every bracket pair defined by unicode.
+    pub fn brackets(mut self, brackets: &[(char, char)]) -> LexerConfig<'s, S> {
+        self.brackets = Some(brackets.to_vec());
+        self
+    }
+
+    /// Set the maximum depth that brackets can be nested to, or `None` for no limit. Exceeding
+    /// the limit causes a `LexError::NestingTooDeep`. Defaults to `DEFAULT_MAX_DEPTH`.
+    ///
+    /// Lexing and printing can handle any depth, but dropping, cloning or comparing a token tree
+    /// recurses once per level so an unlimited depth can overflow the stack on untrusted input.
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> LexerConfig<'s, S> {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Lex `src`, the source of `file`, into a token tree using this config.
+    pub fn lex(&self, file: FileId, src: &'s str) -> Result<TokensBuf<'s, S>, LexError>
+        where S: Clone
+    {
+        lex_with_config(file, src, self)
+    }
+
+    /// Lex `src`, the source of `file`, into a token tree using this config, carrying on past any
+    /// errors. Returns the best-effort token tree along with every error found. Text which
+    /// couldn't be lexed appears in the tree as `TokenKind::Error` tokens and unclosed brackets
+    /// are closed at the end of the input.
+    pub fn lex_recovering(&self, file: FileId, src: &'s str) -> (TokensBuf<'s, S>, Vec<LexError>)
+        where S: Clone
+    {
+        lex_recovering_with_config(file, src, self)
+    }
+
+    /// Re-lex a source after an edit, reusing as much as possible of the token tree lexed from
+    /// the source before the edit. `old` must be the result of lexing `old_src` with this config,
+    /// and `src` the result of applying `edit` to `old_src`. Returns the same result as lexing
+    /// `src` from scratch.
+    ///
+    /// Only the contents of the innermost bracket containing the edit are re-lexed, unless the
+    /// edit changes where that bracket is closed in which case the enclosing brackets are tried
+    /// in turn. Tokens outside the re-lexed bracket are copied over with their positions shifted
+    /// to account for the edit.
+    pub fn relex<'o>(&self, old: &TokensBuf<'o, S>, old_src: &'o str, edit: &Edit, src: &'s str)
+        -> Result<TokensBuf<'s, S>, LexError>
+        where S: Clone
+    {
+        relex_with_config(self, old, old_src, edit, src)
+    }
+
+    pub(crate) fn symbol_table(&self) -> &SymbolTable<'s, S> {
+        &self.symbol_table
+    }
+
+    pub(crate) fn comment_syntax(&self) -> &CommentSyntax<'s> {
+        &self.comments
+    }
+
+    pub(crate) fn get_max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    pub(crate) fn is_quote(&self, c: char) -> bool {
+        self.quotes.contains(&c)
+    }
+
+    pub(crate) fn has_escapes(&self) -> bool {
+        self.escapes
+    }
+
+    pub(crate) fn has_raw_strings(&self) -> bool {
+        self.raw_strings
+    }
+
+    pub(crate) fn has_numbers(&self) -> bool {
+        self.numbers
+    }
+
+    pub(crate) fn is_ident_start(&self, c: char) -> bool {
+        (self.ident_start)(c)
+    }
+
+    pub(crate) fn is_ident_continue(&self, c: char) -> bool {
+        (self.ident_continue)(c)
+    }
+
+    pub(crate) fn is_open_bracket(&self, c: char) -> bool {
+        match self.brackets {
+            None => c.is_open_bracket(),
+            Some(ref brackets) => brackets.iter().any(|&(open, _)| open == c),
+        }
+    }
+
+    pub(crate) fn is_close_bracket(&self, c: char) -> bool {
+        match self.brackets {
+            None => c.is_close_bracket(),
+            Some(ref brackets) => brackets.iter().any(|&(_, close)| close == c),
+        }
+    }
+
+    /// Get the closing bracket for an opening bracket.
+    pub(crate) fn close_bracket(&self, c: char) -> char {
+        match self.brackets {
+            None => c.to_close_bracket(),
+            Some(ref brackets) => {
+                match brackets.iter().find(|&&(open, _)| open == c) {
+                    Some(&(_, close)) => close,
+                    None => c,
+                }
+            },
+        }
+    }
+}
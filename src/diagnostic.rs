@@ -0,0 +1,412 @@
+use crate::span::{TextPos, Span};
+use crate::lex::LexError;
+use crate::line_index::line_bounds;
+use crate::source_map::{FileId, SourceMap};
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// A span of source text with a message attached, used to point out the cause of a
+/// `Diagnostic`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Label {
+    /// The span of text being labelled.
+    pub span: Span,
+    /// The message to show alongside the span.
+    pub message: String,
+    /// Whether this is the main location of the problem. Primary labels are underlined with `^`,
+    /// secondary labels with `-`.
+    pub primary: bool,
+}
+
+/// An error message along with labelled locations in the source, which can be rendered as a
+/// human-readable report.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Diagnostic {
+    /// The headline message.
+    pub message: String,
+    /// The labelled locations. The first primary label is reported as the location of the
+    /// diagnostic.
+    pub labels: Vec<Label>,
+}
+
+/// The span of the single character at `pos`. Zero-length at the end of the source.
+fn char_span(pos: TextPos, src: &str) -> Span {
+    Span {
+        start: pos,
+        end: match pos.next(src) {
+            Some((_, end)) => end,
+            None => pos,
+        },
+    }
+}
+
+fn primary(span: Span, message: &str) -> Label {
+    Label {
+        span,
+        message: String::from(message),
+        primary: true,
+    }
+}
+
+fn secondary(span: Span, message: &str) -> Label {
+    Label {
+        span,
+        message: String::from(message),
+        primary: false,
+    }
+}
+
+/// How to decorate the parts of a rendered diagnostic.
+struct Style {
+    error: &'static str,
+    bold: &'static str,
+    gutter: &'static str,
+    primary: &'static str,
+    secondary: &'static str,
+    reset: &'static str,
+}
+
+const PLAIN: Style = Style {
+    error: "",
+    bold: "",
+    gutter: "",
+    primary: "",
+    secondary: "",
+    reset: "",
+};
+
+const ANSI: Style = Style {
+    error: "\x1b[1;31m",
+    bold: "\x1b[1m",
+    gutter: "\x1b[1;34m",
+    primary: "\x1b[1;31m",
+    secondary: "\x1b[1;34m",
+    reset: "\x1b[0m",
+};
+
+impl Diagnostic {
+    /// Build a diagnostic describing a `LexError`. `src` must be the source text that was being
+    /// lexed.
+    pub fn from_lex_error(error: &LexError, src: &str) -> Diagnostic {
+        let (message, labels) = match *error {
+            LexError::InvalidSymbolTable(ref e) => {
+                (format!("invalid symbol table: {}", e), vec![])
+            },
+            LexError::InvalidClosingBracket { open_pos, close_pos } => {
+                (String::from("mismatched closing bracket"), vec![
+                    primary(char_span(close_pos, src), "does not match the opening bracket"),
+                    secondary(char_span(open_pos, src), "opening bracket"),
+                ])
+            },
+            LexError::UnclosedBracket { open_pos } => {
+                (String::from("unclosed bracket"), vec![
+                    primary(char_span(open_pos, src), "this bracket is never closed"),
+                ])
+            },
+            LexError::UnexpectedChar { pos, c } => {
+                (format!("unexpected character {:?}", c), vec![
+                    primary(char_span(pos, src), "unexpected character"),
+                ])
+            },
+            LexError::UnexpectedClosingBracket { pos, c } => {
+                (format!("unexpected closing bracket {:?}", c), vec![
+                    primary(char_span(pos, src), "no matching opening bracket"),
+                ])
+            },
+            LexError::UnclosedString { start_pos } => {
+                (String::from("unclosed string literal"), vec![
+                    primary(char_span(start_pos, src), "string literal starts here"),
+                ])
+            },
+            LexError::InvalidEscapeDigit { c, pos } => {
+                (format!("invalid hex digit {:?} in escape sequence", c), vec![
+                    primary(char_span(pos, src), "expected a hex digit"),
+                ])
+            },
+            LexError::InvalidEscapeCode { code, pos } => {
+                (format!("invalid character code {:#x} in escape sequence", code), vec![
+                    primary(char_span(pos, src), "not a valid unicode scalar value"),
+                ])
+            },
+            LexError::InvalidEscapeChar { c, pos } => {
+                (format!("unknown escape sequence \\{}", c), vec![
+                    primary(char_span(pos, src), "unknown escape character"),
+                ])
+            },
+            LexError::InvalidUnicodeEscape { pos } => {
+                (String::from("unicode escape sequence has too many digits"), vec![
+                    primary(char_span(pos, src), "expected at most 6 hex digits"),
+                ])
+            },
+            LexError::InvalidUnicodeEscapeSyntax { pos } => {
+                (String::from("invalid unicode escape sequence"), vec![
+                    primary(char_span(pos, src), "expected `{`"),
+                ])
+            },
+            LexError::InvalidDigit { c, radix, pos } => {
+                (format!("invalid digit {:?} in base {} literal", c, radix), vec![
+                    primary(char_span(pos, src), "invalid digit"),
+                ])
+            },
+            LexError::MissingDigits { pos } => {
+                (String::from("missing digits in numeric literal"), vec![
+                    primary(char_span(pos, src), "expected at least one digit"),
+                ])
+            },
+            LexError::MissingExponentDigits { pos } => {
+                (String::from("missing digits in exponent"), vec![
+                    primary(char_span(pos, src), "expected at least one digit"),
+                ])
+            },
+            LexError::UnclosedComment { start_pos } => {
+                (String::from("unclosed block comment"), vec![
+                    primary(char_span(start_pos, src), "comment starts here"),
+                ])
+            },
+            LexError::UnclosedRawString { start_pos, hashes } => {
+                let fence: String = core::iter::repeat_n('#', hashes).collect();
+                (String::from("unclosed raw string literal"), vec![
+                    primary(char_span(start_pos, src),
+                            &format!("expected a closing `\"{}`", fence)),
+                ])
+            },
+            LexError::NestingTooDeep { pos } => {
+                (String::from("brackets nested too deeply"), vec![
+                    primary(char_span(pos, src), "exceeds the maximum nesting depth"),
+                ])
+            },
+        };
+        Diagnostic {
+            message,
+            labels,
+        }
+    }
+
+    /// Render the diagnostic as plain text. `file_name` is only used for display.
+    pub fn render(&self, file_name: &str, src: &str) -> String {
+        self.render_with_style(&Sources::Single(file_name, src), &PLAIN)
+    }
+
+    /// Render the diagnostic as text coloured with ANSI escape codes. `file_name` is only used
+    /// for display.
+    pub fn render_ansi(&self, file_name: &str, src: &str) -> String {
+        self.render_with_style(&Sources::Single(file_name, src), &ANSI)
+    }
+
+    /// Render the diagnostic as plain text, looking up the name and source of each label's file
+    /// in `files`. Labels in files other than the one the diagnostic is reported in are shown
+    /// after a `:::` line naming their file.
+    pub fn render_in(&self, files: &SourceMap) -> String {
+        self.render_with_style(&Sources::Map(files), &PLAIN)
+    }
+
+    /// Render the diagnostic as text coloured with ANSI escape codes, looking up the name and
+    /// source of each label's file in `files`.
+    pub fn render_ansi_in(&self, files: &SourceMap) -> String {
+        self.render_with_style(&Sources::Map(files), &ANSI)
+    }
+
+    fn render_with_style(&self, sources: &Sources, style: &Style) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{}error{}{}: {}{}",
+                         style.error, style.reset, style.bold, self.message, style.reset);
+
+        let location = self.labels.iter().find(|l| l.primary).or(self.labels.first());
+        let location = match location {
+            Some(label) => label.span.start,
+            None => {
+                if let Sources::Single(file_name, _) = *sources {
+                    let _ = writeln!(out, "{} -->{} {}", style.gutter, style.reset, file_name);
+                }
+                return out;
+            },
+        };
+
+        // Labels in the same file as the location come first.
+        let mut labels: Vec<&Label> = self.labels.iter().collect();
+        labels.sort_by_key(|l| {
+            let file = l.span.file();
+            (file != location.file, file, l.span.start.line, !l.primary, l.span.start.col)
+        });
+        let max_line = labels.iter().map(|l| l.span.start.line + 1).max().unwrap_or(1);
+        let gutter_width = max_line.to_string().len();
+        let blank = " ".repeat(gutter_width);
+
+        let mut last_line = None;
+        for label in labels {
+            let start = label.span.start;
+            let (file_name, src) = sources.get(start.file);
+            if last_line.map(|(file, _)| file) != Some(start.file) {
+                let (arrow, pos) = match last_line {
+                    None => ("-->", location),
+                    Some(_) => (":::", start),
+                };
+                let _ = writeln!(out, "{}{}{}{} {}:{}",
+                                 blank, style.gutter, arrow, style.reset, file_name, pos);
+                let _ = writeln!(out, "{} {}|{}", blank, style.gutter, style.reset);
+            }
+
+            let Range { start: line_start, end: line_end } = line_bounds(src, start.byte);
+            let line = &src[line_start..line_end];
+
+            if last_line != Some((start.file, start.line)) {
+                let _ = writeln!(out, "{}{:>width$} |{} {}",
+                                 style.gutter, start.line + 1, style.reset, line.replace('\t', " "),
+                                 width = gutter_width);
+                last_line = Some((start.file, start.line));
+            }
+
+            // Measure the underline using the widths tracked by `TextPos`. Spans running past
+            // the end of the line are cut off there. `TextPos` gives tabs zero width but they're
+            // rendered as a space so they need to be added back in.
+            let end_byte = match label.span.end.line == start.line {
+                true => label.span.end.byte,
+                false => line_start + line.len(),
+            };
+            let mut end = start;
+            while end.byte < end_byte {
+                end = match end.next(src) {
+                    Some((_, p)) => p,
+                    None => break,
+                };
+            }
+            let tabs_before = src[line_start..start.byte].matches('\t').count();
+            let tabs_within = src[start.byte..end.byte].matches('\t').count();
+            let width = end.col.saturating_sub(start.col) + tabs_within;
+            let (marker, colour) = match label.primary {
+                true => ('^', style.primary),
+                false => ('-', style.secondary),
+            };
+            let underline: String = core::iter::repeat_n(marker, width.max(1)).collect();
+            let _ = writeln!(out, "{} {}|{} {}{}{} {}{}",
+                             blank, style.gutter, style.reset,
+                             " ".repeat(start.col + tabs_before),
+                             colour, underline, label.message, style.reset);
+        }
+        out
+    }
+}
+
+/// Where to find the names and sources of the files a diagnostic refers to.
+enum Sources<'a, 's> {
+    /// A single file, which every label is assumed to be in.
+    Single(&'a str, &'a str),
+    Map(&'a SourceMap<'s>),
+}
+
+impl<'a, 's> Sources<'a, 's> {
+    /// Get the name and source of a file.
+    fn get(&self, file: FileId) -> (&'a str, &'a str) {
+        match *self {
+            Sources::Single(name, src) => (name, src),
+            Sources::Map(map) => (map.name(file), map.src(file)),
+        }
+    }
+}
+
+impl LexError {
+    /// Render this error as a human-readable report pointing into `src`. See `Diagnostic` for
+    /// more control over the output.
+    pub fn render(&self, file_name: &str, src: &str) -> String {
+        Diagnostic::from_lex_error(self, src).render(file_name, src)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lex::lex;
+
+    #[test]
+    fn test_render() {
+        let src = "foo(\n  a]\n";
+        let err = lex(FileId(0), src, &[]).unwrap_err();
+        assert_eq!(err.render("test.malk", src), "\
+error: mismatched closing bracket
+ --> test.malk:2:4
+  |
+1 | foo(
+  |    - opening bracket
+2 |   a]
+  |    ^ does not match the opening bracket
+");
+
+        let src = "\tx = \"abc";
+        let err = lex(FileId(0), src, &["="]).unwrap_err();
+        assert_eq!(err.render("tab.malk", src), "\
+error: unclosed string literal
+ --> tab.malk:1:5
+  |
+1 |  x = \"abc
+  |      ^ string literal starts here
+");
+
+        let src = "foo(\r\r\n  a]";
+        let err = lex(FileId(0), src, &[]).unwrap_err();
+        assert_eq!(err.render("cr.malk", src), "\
+error: mismatched closing bracket
+ --> cr.malk:3:4
+  |
+1 | foo(
+  |    - opening bracket
+3 |   a]
+  |    ^ does not match the opening bracket
+");
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        let src = "foo(\n  a]\n";
+        let err = lex(FileId(0), src, &[]).unwrap_err();
+        let diagnostic = Diagnostic::from_lex_error(&err, src);
+        assert_eq!(diagnostic.render_ansi("test.malk", src), concat!(
+            "\x1b[1;31merror\x1b[0m\x1b[1m: mismatched closing bracket\x1b[0m\n",
+            " \x1b[1;34m-->\x1b[0m test.malk:2:4\n",
+            "  \x1b[1;34m|\x1b[0m\n",
+            "\x1b[1;34m1 |\x1b[0m foo(\n",
+            "  \x1b[1;34m|\x1b[0m    \x1b[1;34m- opening bracket\x1b[0m\n",
+            "\x1b[1;34m2 |\x1b[0m   a]\n",
+            "  \x1b[1;34m|\x1b[0m    \x1b[1;31m^ does not match the opening bracket\x1b[0m\n",
+        ));
+    }
+
+    #[test]
+    fn test_render_in() {
+        let mut files = SourceMap::new();
+        let a = files.add_file("a.malk", "let x = 1;");
+        let b = files.add_file("b.malk", "\n  x");
+        let span = |file, start, end| {
+            let lines = files.line_index(file);
+            Span {
+                start: lines.pos(start).unwrap(),
+                end: lines.pos(end).unwrap(),
+            }
+        };
+        let diagnostic = Diagnostic {
+            message: String::from("`x` is defined twice"),
+            labels: vec![
+                secondary(span(a, 4, 5), "first defined here"),
+                primary(span(b, 3, 4), "defined again here"),
+            ],
+        };
+        assert_eq!(diagnostic.render_in(&files), "\
+error: `x` is defined twice
+ --> b.malk:2:3
+  |
+2 |   x
+  |   ^ defined again here
+ ::: a.malk:1:5
+  |
+1 | let x = 1;
+  |     - first defined here
+");
+    }
+}
@@ -1,16 +1,27 @@
-use token::{Token, TokenKind};
-use tokens::TokensBuf;
-use span::TextPos;
-use symbol_table::{InvalidSymbolTableError, validate_symbol_table};
+use crate::token::{Token, TokenKind, StringLit, NumberLit};
+use crate::tokens::TokensBuf;
+use crate::span::{TextPos, Span};
+use crate::symbol_table::{InvalidSymbolTableError, SymbolTable, validate_symbol_table};
+use crate::config::{CommentSyntax, LexerConfig};
+use crate::stream::{FlatToken, Lexer};
+use crate::source_map::FileId;
 
-use std::str::pattern::Pattern;
-use std::borrow::Cow;
-use std::char;
-use unicode_brackets::UnicodeBrackets;
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::char;
+use core::convert::Infallible;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum LexError<'s> {
-    InvalidSymbolTable(InvalidSymbolTableError<'s>),
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LexError {
+    InvalidSymbolTable(InvalidSymbolTableError),
     InvalidClosingBracket {
         open_pos: TextPos,
         close_pos: TextPos,
@@ -47,359 +58,810 @@ pub enum LexError<'s> {
     InvalidUnicodeEscapeSyntax {
         pos: TextPos,
     },
+    InvalidDigit {
+        c: char,
+        radix: u32,
+        pos: TextPos,
+    },
+    MissingDigits {
+        pos: TextPos,
+    },
+    MissingExponentDigits {
+        pos: TextPos,
+    },
+    UnclosedComment {
+        start_pos: TextPos,
+    },
+    UnclosedRawString {
+        start_pos: TextPos,
+        hashes: usize,
+    },
+    NestingTooDeep {
+        pos: TextPos,
+    },
 }
 
-/*
-impl error::Error for LexError {
-    fn cause(&self) -> Option<&error::Error> {
+impl LexError {
+    /// The position the error is reported at, or `None` for an invalid symbol table. Its `file`
+    /// identifies the file that was being lexed.
+    pub fn pos(&self) -> Option<TextPos> {
         match *self {
-            InvalidSymbolTable(ref e) => Some(e),
-            _ => None,
+            LexError::InvalidSymbolTable(_) => None,
+            LexError::InvalidClosingBracket { close_pos, .. } => Some(close_pos),
+            LexError::UnclosedBracket { open_pos } => Some(open_pos),
+            LexError::UnclosedString { start_pos } |
+            LexError::UnclosedComment { start_pos } |
+            LexError::UnclosedRawString { start_pos, .. } => Some(start_pos),
+            LexError::UnexpectedChar { pos, .. } |
+            LexError::UnexpectedClosingBracket { pos, .. } |
+            LexError::InvalidEscapeDigit { pos, .. } |
+            LexError::InvalidEscapeCode { pos, .. } |
+            LexError::InvalidEscapeChar { pos, .. } |
+            LexError::InvalidUnicodeEscape { pos } |
+            LexError::InvalidUnicodeEscapeSyntax { pos } |
+            LexError::InvalidDigit { pos, .. } |
+            LexError::MissingDigits { pos } |
+            LexError::MissingExponentDigits { pos } |
+            LexError::NestingTooDeep { pos } => Some(pos),
         }
     }
+}
 
-    fn description(&self) -> &str {
+#[cfg(feature = "std")]
+impl error::Error for LexError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            InvalidSymbolTable(_) => "Invalid symbol table",
-            InvalidClosingBracket { .. } => "Invalid closing bracket",
-            UnclosedBracket => "Unclosed bracket",
-            UnexpectedChar => "Unexpected char",
-            UnexpectedClosingBracket => "Unexpected closing bracket",
-            UnclosedString => "Unclosed string",
-            InvalidEscapeDigit => "Invalid escape digit",
-            InvalidEscapeCode => "Invalid escape code",
-            InvalidEscapeChar => "Invalid escape character",
-        },
+            LexError::InvalidSymbolTable(ref e) => Some(e),
+            _ => None,
+        }
     }
 }
 
 impl fmt::Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            InvalidSymbolTable(ref e) => {
-                write!("Invalid symbol table: {}", e),
+            LexError::InvalidSymbolTable(ref e) => {
+                write!(f, "invalid symbol table: {}", e)
+            },
+            LexError::InvalidClosingBracket { open_pos, close_pos } => {
+                write!(f, "{}: closing bracket does not match the opening bracket at {}",
+                       close_pos, open_pos)
+            },
+            LexError::UnclosedBracket { open_pos } => {
+                write!(f, "{}: unclosed bracket", open_pos)
+            },
+            LexError::UnexpectedChar { pos, c } => {
+                write!(f, "{}: unexpected character {:?}", pos, c)
+            },
+            LexError::UnexpectedClosingBracket { pos, c } => {
+                write!(f, "{}: unexpected closing bracket {:?}", pos, c)
+            },
+            LexError::UnclosedString { start_pos } => {
+                write!(f, "{}: unclosed string literal", start_pos)
+            },
+            LexError::InvalidEscapeDigit { c, pos } => {
+                write!(f, "{}: invalid hex digit {:?} in escape sequence", pos, c)
+            },
+            LexError::InvalidEscapeCode { code, pos } => {
+                write!(f, "{}: invalid character code {:#x} in escape sequence", pos, code)
             },
-            InvalidClosingBracket {
-                open_pos: TextPos,
-                close_pos: TextPos,
-            } => {
-                write!("Invalid closing bracket"),
+            LexError::InvalidEscapeChar { c, pos } => {
+                write!(f, "{}: unknown escape sequence \\{}", pos, c)
             },
-            UnclosedBracket {
-                open_pos: TextPos,
+            LexError::InvalidUnicodeEscape { pos } => {
+                write!(f, "{}: unicode escape sequence has too many digits", pos)
             },
-            UnexpectedChar {
-                pos: TextPos,
-                c: char,
+            LexError::InvalidUnicodeEscapeSyntax { pos } => {
+                write!(f, "{}: expected `{{` in unicode escape sequence", pos)
             },
-            UnexpectedClosingBracket {
-                pos: TextPos,
-                c: char,
+            LexError::InvalidDigit { c, radix, pos } => {
+                write!(f, "{}: invalid digit {:?} in base {} literal", pos, c, radix)
             },
-            UnclosedString {
-                start_pos: TextPos,
+            LexError::MissingDigits { pos } => {
+                write!(f, "{}: expected at least one digit in numeric literal", pos)
             },
-            InvalidEscapeDigit {
-                c: char,
-                pos: TextPos,
+            LexError::MissingExponentDigits { pos } => {
+                write!(f, "{}: expected at least one digit in exponent", pos)
             },
-            InvalidEscapeCode {
-                code: u32,
-                pos: TextPos,
+            LexError::UnclosedComment { start_pos } => {
+                write!(f, "{}: unclosed block comment", start_pos)
             },
-            InvalidEscapeChar {
-                c: char,
-                pos: TextPos,
+            LexError::UnclosedRawString { start_pos, hashes } => {
+                let fence: String = core::iter::repeat_n('#', hashes).collect();
+                write!(f, "{}: unclosed raw string literal, expected a closing `\"{}`", start_pos, fence)
+            },
+            LexError::NestingTooDeep { pos } => {
+                write!(f, "{}: brackets nested too deeply", pos)
             },
         }
     }
 }
-*/
-
-/// The result of a succesful call to sub_lex
-struct SubLex<'s> {
-    /// The tokens succesfully lexed.
-    tokens: TokensBuf<'s>,
-    /// If lexing ended by hitting a closing bracket, the bracket and the position after it.
-    terminator: Option<(char, TextPos)>,
+
+/// Consume a run of digits of the given radix, along with any `_` separators. Returns the end
+/// of the run. Errors if the run doesn't contain at least one digit or is followed by a decimal
+/// digit which isn't valid in this radix.
+fn lex_digits(start: TextPos, src: &str, radix: u32) -> Result<TextPos, LexError> {
+    let mut end = start;
+    let mut seen_digit = false;
+    while let Some((c, p)) = end.next(src) {
+        if c.is_digit(radix) {
+            seen_digit = true;
+        }
+        else if c.is_ascii_digit() {
+            return Err(LexError::InvalidDigit {
+                c,
+                radix,
+                pos: end,
+            });
+        }
+        else if c != '_' {
+            break;
+        }
+        end = p;
+    }
+    if !seen_digit {
+        return Err(LexError::MissingDigits {
+            pos: start,
+        });
+    }
+    Ok(end)
 }
 
-fn sub_lex<'s>(start: TextPos, src: &'s str, symbols: &[&'s str]) -> Result<SubLex<'s>, LexError<'s>> {
-    let mut tokens = Vec::new();
-    let mut pos = start;
-    'main: loop {
-        let (c, p) = match pos.next(src) {
-            Some(x) => x,
-            None => return Ok(SubLex {
-                tokens: TokensBuf {
-                    tokens: tokens,
-                    end: pos,
-                },
-                terminator: None,
-            }),
-        };
-        if c.is_whitespace() {
-            let mut end = p;
-            loop {
-                let (c, p) = match end.next(src) {
-                    Some(x) => x,
-                    None => break,
+/// Lex a numeric literal starting at `start`, which must point to a decimal digit. Returns the
+/// literal and the position after it.
+fn lex_number<'s, S>(start: TextPos,
+                     src: &'s str,
+                     config: &LexerConfig<S>) -> Result<(NumberLit<'s>, TextPos), LexError>
+{
+    let mut radix = 10;
+    let mut int_start = start;
+    if let Some(('0', p)) = start.next(src) {
+        if let Some((c, prefix_end)) = p.next(src) {
+            let prefix_radix = match c {
+                'x' => Some(16),
+                'o' => Some(8),
+                'b' => Some(2),
+                _ => None,
+            };
+            if let Some(prefix_radix) = prefix_radix {
+                radix = prefix_radix;
+                int_start = prefix_end;
+            }
+        }
+    }
+
+    let int_end = lex_digits(int_start, src, radix)?;
+    let mut end = int_end;
+
+    let mut fraction = None;
+    let mut exponent = None;
+    if radix == 10 {
+        if let Some(('.', frac_start)) = end.next(src) {
+            if let Some((c, _)) = frac_start.next(src) {
+                if c.is_ascii_digit() {
+                    let frac_end = lex_digits(frac_start, src, 10)?;
+                    fraction = Some(&src[frac_start.byte..frac_end.byte]);
+                    end = frac_end;
+                }
+            }
+        }
+
+        if let Some((c, exp_start)) = end.next(src) {
+            if c == 'e' || c == 'E' {
+                let digits_start = match exp_start.next(src) {
+                    Some(('+', p)) | Some(('-', p)) => p,
+                    _ => exp_start,
                 };
-                if !c.is_whitespace() {
+                let exp_end = match lex_digits(digits_start, src, 10) {
+                    Ok(exp_end) => exp_end,
+                    Err(LexError::MissingDigits { pos }) => {
+                        return Err(LexError::MissingExponentDigits {
+                            pos,
+                        });
+                    },
+                    Err(e) => return Err(e),
+                };
+                exponent = Some(&src[exp_start.byte..exp_end.byte]);
+                end = exp_end;
+            }
+        }
+    }
+
+    let mut suffix = None;
+    if let Some((c, p)) = end.next(src) {
+        if config.is_ident_start(c) {
+            let suffix_start = end;
+            end = p;
+            while let Some((c, p)) = end.next(src) {
+                if !config.is_ident_continue(c) {
                     break;
                 }
                 end = p;
             }
+            suffix = Some(&src[suffix_start.byte..end.byte]);
+        }
+    }
 
-            let token = Token {
-                kind: TokenKind::Whitespace(&src[pos.byte..end.byte]),
-                start: pos,
-            };
+    let number = NumberLit {
+        raw: &src[start.byte..end.byte],
+        radix,
+        integer: &src[int_start.byte..int_end.byte],
+        fraction,
+        exponent,
+        suffix,
+    };
+    Ok((number, end))
+}
 
-            tokens.push(token);
-            pos = end;
-            continue;
-        }
-        if c.is_xid_start() {
-            let mut end = p;
-            loop {
-                let (c, p) = match end.next(src) {
-                    Some(x) => x,
-                    None => break,
-                };
-                if !c.is_xid_continue() {
+/// Advance `pos` past `s`, which must be the text at `pos`.
+fn skip_str(mut pos: TextPos, src: &str, s: &str) -> TextPos {
+    for _ in s.chars() {
+        pos = match pos.next(src) {
+            Some((_, p)) => p,
+            None => break,
+        };
+    }
+    pos
+}
+
+/// If a comment starts at `start`, lex it and return the position after it. The longest
+/// matching comment introducer is used.
+fn lex_comment(start: TextPos, src: &str, comments: &CommentSyntax)
+    -> Result<Option<TextPos>, LexError>
+{
+    let rest = &src[start.byte..];
+    let mut line_intro: Option<&str> = None;
+    for intro in comments.line.iter() {
+        if !intro.is_empty() && rest.starts_with(intro)
+            && line_intro.is_none_or(|l| intro.len() > l.len()) {
+                line_intro = Some(intro);
+            }
+    }
+    let mut block_delims: Option<(&str, &str)> = None;
+    for &(open, close) in comments.block.iter() {
+        if !open.is_empty() && !close.is_empty() && rest.starts_with(open)
+            && block_delims.is_none_or(|(o, _)| open.len() > o.len()) {
+                block_delims = Some((open, close));
+            }
+    }
+
+    if let Some(intro) = line_intro {
+        if block_delims.is_none_or(|(open, _)| intro.len() >= open.len()) {
+            let mut end = skip_str(start, src, intro);
+            while let Some((c, p)) = end.next(src) {
+                if c == '\n' || c == '\r' {
                     break;
                 }
                 end = p;
             }
+            return Ok(Some(end));
+        }
+    }
 
-            let token = Token {
-                kind: TokenKind::Ident(&src[pos.byte..end.byte]),
-                start: pos,
-            };
-
-            tokens.push(token);
-            pos = end;
+    let (open, close) = match block_delims {
+        Some(delims) => delims,
+        None => return Ok(None),
+    };
+    let mut depth = 1;
+    let mut end = skip_str(start, src, open);
+    loop {
+        let rest = &src[end.byte..];
+        if rest.starts_with(close) {
+            end = skip_str(end, src, close);
+            depth -= 1;
+            if depth == 0 {
+                return Ok(Some(end));
+            }
             continue;
         }
-        if c.is_open_bracket() {
-            let sub = try!(sub_lex(p, src, symbols));
-            match sub.terminator {
-                Some((term_char, new_end)) => {
-                    if term_char == c.to_close_bracket() {
-                        let kind = TokenKind::Bracket(c, sub.tokens);
-                        let token = Token {
-                            kind: kind,
-                            start: pos,
-                        };
-                        tokens.push(token);
-                        pos = new_end;
-                        continue;
-                    }
-                    else {
-                        return Err(LexError::InvalidClosingBracket {
-                            open_pos: pos,
-                            close_pos: sub.tokens.end,
-                        });
-                    }
-                },
-                None => {
-                    return Err(LexError::UnclosedBracket {
-                        open_pos: pos,
-                    });
-                },
+        if comments.nested && rest.starts_with(open) {
+            end = skip_str(end, src, open);
+            depth += 1;
+            continue;
+        }
+        end = match end.next(src) {
+            Some((_, p)) => p,
+            None => return Err(LexError::UnclosedComment {
+                start_pos: start,
+            }),
+        };
+    }
+}
+
+/// If a raw string starts at `start`, lex it and return it along with the position after it.
+fn lex_raw_string<'s>(start: TextPos, src: &'s str) -> Result<Option<(StringLit<'s>, TextPos)>, LexError> {
+    let mut p = match start.next(src) {
+        Some(('r', p)) => p,
+        _ => return Ok(None),
+    };
+    let mut hashes = 0;
+    loop {
+        match p.next(src) {
+            Some(('#', new_p)) => {
+                hashes += 1;
+                p = new_p;
+            },
+            Some(('"', new_p)) => {
+                p = new_p;
+                break;
+            },
+            _ => return Ok(None),
+        }
+    }
+
+    let string_start = p;
+    loop {
+        let (c, new_p) = match p.next(src) {
+            Some(x) => x,
+            None => return Err(LexError::UnclosedRawString {
+                start_pos: start,
+                hashes,
+            }),
+        };
+        if c == '"' {
+            let rest = &src[new_p.byte..];
+            let closed = rest.as_bytes().get(..hashes).is_some_and(|b| b.iter().all(|&b| b == b'#'));
+            if closed {
+                let raw = &src[string_start.byte..p.byte];
+                let string = StringLit {
+                    quote: '"',
+                    hashes: Some(hashes),
+                    raw,
+                    value: Cow::Borrowed(raw),
+                };
+                return Ok(Some((string, skip_str(new_p, src, &rest[..hashes]))));
             }
         }
-        if c.is_close_bracket() {
-            return Ok(SubLex {
-                tokens: TokensBuf {
-                    tokens: tokens,
-                    end: pos,
-                },
-                terminator: Some((c, p)),
+        p = new_p;
+    }
+}
+
+/// Parse an escape sequence in a string literal. `string_pos` is the position of the start of
+/// the string literal and `pos` is the position of the backslash. Returns the escaped character
+/// and the position after the escape sequence.
+fn lex_escape(string_pos: TextPos, pos: TextPos, src: &str) -> Result<(char, TextPos), LexError> {
+    let next = |some_pos: TextPos| match some_pos.next(src) {
+        Some(x) => Ok(x),
+        None => Err(LexError::UnclosedString {
+            start_pos: string_pos,
+        }),
+    };
+    let from_hex = |some_char, its_pos| match some_char {
+        '0'..='9' => Ok(some_char as u32 - '0' as u32),
+        'a'..='f' => Ok(some_char as u32 - 'a' as u32 + 10),
+        _ => Err(LexError::InvalidEscapeDigit {
+            c: some_char,
+            pos: its_pos,
+        }),
+    };
+    let from_u32 = |some_u32, esc_pos| match char::from_u32(some_u32) {
+        Some(c) => Ok(c),
+        None => Err(LexError::InvalidEscapeCode {
+            code: some_u32,
+            pos: esc_pos,
+        }),
+    };
+
+    let (_, p) = next(pos)?;
+    let (esc_c, esc_p) = next(p)?;
+    let escaped = match esc_c {
+        '\'' => ('\'', esc_p),
+        '"'  => ('"',  esc_p),
+        '0'  => ('\0', esc_p),
+        't'  => ('\t', esc_p),
+        'n'  => ('\n', esc_p),
+        'r'  => ('\r', esc_p),
+        '\\' => ('\\', esc_p),
+        'x' => {
+            let (nib0, nib0_end) = next(esc_p)?;
+            let (nib1, nib1_end) = next(nib0_end)?;
+            let nib0 = from_hex(nib0, esc_p)?;
+            let nib1 = from_hex(nib1, nib0_end)?;
+            (from_u32((nib0 << 4) | nib1, pos)?, nib1_end)
+        },
+        'u' => {
+            let (open_c, open_p) = next(esc_p)?;
+            if open_c != '{' {
+                return Err(LexError::InvalidUnicodeEscapeSyntax {
+                    pos: esc_p,
+                });
+            }
+
+            let mut code = 0u32;
+            let mut end = open_p;
+            let mut found_end = false;
+            for _ in 0..6 {
+                let (nib, nib_end) = next(end)?;
+                if nib == '}' {
+                    end = nib_end;
+                    found_end = true;
+                    break;
+                }
+                let nib = from_hex(nib, end)?;
+                code = (code << 4) | nib;
+                end = nib_end;
+            }
+            if !found_end {
+                let (close_c, close_p) = next(end)?;
+                if close_c != '}' {
+                    return Err(LexError::InvalidUnicodeEscape {
+                        pos,
+                    });
+                }
+                end = close_p;
+            }
+            (from_u32(code, pos)?, end)
+        },
+        _   => {
+            return Err(LexError::InvalidEscapeChar {
+                c: esc_c,
+                pos: p,
             });
-        }
-        if c == '\'' || c == '"' {
-            let token_start = pos;
-            let next = |some_pos: TextPos| match some_pos.next(src) {
-                Some(x) => Ok(x),
-                None => Err(LexError::UnclosedString {
-                    start_pos: token_start,
-                }),
+        },
+    };
+    Ok(escaped)
+}
+
+/// Lex a string literal starting at `start`, which must point to a quote character. Returns the
+/// token and the position after it.
+fn lex_string<'s, S>(start: TextPos,
+                     src: &'s str,
+                     config: &LexerConfig<'s, S>,
+                     errors: &mut Errors) -> Result<(TokenKind<'s, S>, TextPos), LexError>
+{
+    let (quote, string_start) = start.next(src).unwrap();
+    let mut owned = None;
+    let mut p = string_start;
+    while let Some((new_c, new_p)) = p.next(src) {
+        if new_c == quote {
+            let cow = match owned {
+                Some(s) => Cow::Owned(s),
+                None => Cow::Borrowed(&src[string_start.byte..p.byte]),
             };
-            let from_hex = |some_char, its_pos| match some_char {
-                '0'...'9' => Ok(some_char as u32 - '0' as u32),
-                'a'...'f' => Ok(some_char as u32 - 'a' as u32 + 10),
-                _ => Err(LexError::InvalidEscapeDigit {
-                    c: some_char,
-                    pos: its_pos,
-                }),
+            let kind = TokenKind::String(StringLit {
+                quote,
+                hashes: None,
+                raw: &src[string_start.byte..p.byte],
+                value: cow,
+            });
+            return Ok((kind, new_p));
+        }
+        if new_c == '\\' && config.has_escapes() {
+            let (unescaped, unescaped_end) = match lex_escape(start, p, src) {
+                Ok(x) => x,
+                Err(LexError::UnclosedString { .. }) => break,
+                Err(e) => {
+                    errors.report(e)?;
+                    let (_, skip_end) = new_p.next(src).unwrap();
+                    ('\u{fffd}', skip_end)
+                },
             };
-            let from_u32 = |some_u32, esc_pos| match char::from_u32(some_u32) {
-                Some(c) => Ok(c),
-                None => Err(LexError::InvalidEscapeCode {
-                    code: some_u32,
-                    pos: esc_pos,
-                }),
+            let took = owned.take();
+            let mut s = match took {
+                Some(s) => s,
+                None => String::from(&src[string_start.byte..p.byte]),
             };
-            let mut owned = None;
-            let string_start = p;
-            let mut p = p;
-            loop {
-                let (new_c, new_p) = try!(next(p));
-                if new_c == c {
-                    let cow = match owned {
-                        Some(s) => Cow::Owned(s),
-                        None => Cow::Borrowed(&src[string_start.byte..p.byte]),
-                    };
-                    let kind = TokenKind::String(cow);
-                    let token = Token {
-                        kind: kind,
-                        start: pos,
-                    };
-                    tokens.push(token);
-                    pos = new_p;
-                    continue 'main;
-                }
-                if new_c == '\\' {
-                    let (esc_c, esc_p) = try!(next(new_p));
-                    let (unescaped, unescaped_end) = match esc_c {
-                        '\'' => ('\'', esc_p),
-                        '"'  => ('"',  esc_p),
-                        '0'  => ('\0', esc_p),
-                        't'  => ('\t', esc_p),
-                        'n'  => ('\n', esc_p),
-                        'r'  => ('\r', esc_p),
-                        '\\' => ('\\', esc_p),
-                        'x' => {
-                            let (nib0, nib0_end) = try!(next(esc_p));
-                            let (nib1, nib1_end) = try!(next(nib0_end));
-                            let nib0 = try!(from_hex(nib0, esc_p));
-                            let nib1 = try!(from_hex(nib1, nib0_end));
-                            (try!(from_u32((nib0 << 4) | nib1, p)), nib1_end)
-                        },
-                        'u' => {
-                            let (open_c, open_p) = try!(next(esc_p));
-                            if open_c != '{' {
-                                return Err(LexError::InvalidUnicodeEscapeSyntax {
-                                    pos: esc_p,
-                                });
-                            }
-
-                            let mut code = 0u32;
-                            let mut end = open_p;
-                            let mut found_end = false;
-                            for _ in 0..6 {
-                                let (nib, nib_end) = try!(next(end));
-                                if nib == '}' {
-                                    end = nib_end;
-                                    found_end = true;
-                                    break;
-                                }
-                                let nib = try!(from_hex(nib, end));
-                                code = (code << 4) | nib;
-                                end = nib_end;
-                            }
-                            if !found_end {
-                                let (close_c, close_p) = try!(next(end));
-                                if close_c != '}' {
-                                    return Err(LexError::InvalidUnicodeEscape {
-                                        pos: p,
-                                    });
-                                }
-                                end = close_p;
-                            }
-                            (try!(from_u32(code, p)), end)
-                        },
-                        _   => {
-                            return Err(LexError::InvalidEscapeChar {
-                                c: esc_c,
-                                pos: new_p,
-                            });
-                        },
-                    };
-                    let took = owned.take();
-                    let mut s = match took {
-                        Some(s) => s,
-                        None => String::from(&src[string_start.byte..p.byte]),
-                    };
-                    s.push(unescaped);
-                    owned = Some(s);
-                    p = unescaped_end;
-                    continue;
-                }
-                p = new_p;
-            }
+            s.push(unescaped);
+            owned = Some(s);
+            p = unescaped_end;
+            continue;
         }
+        p = new_p;
+    }
 
-        let mut sym_end = p;
-        loop {
-            let sym_prefix = &src[pos.byte..sym_end.byte];
-            let mut seen = false;
-            let mut is_symbol = false;
-            for this_symbol in symbols {
-                if sym_prefix.is_prefix_of(this_symbol) {
-                    match seen {
-                        true => {
-                            is_symbol = false;
-                            break;
-                        },
-                        false => {
-                            if this_symbol.len() == sym_prefix.len() {
-                                is_symbol = true;
-                            }
-                            seen = true;
-                        },
-                    }
-                }
+    errors.report(LexError::UnclosedString {
+        start_pos: start,
+    })?;
+    let end = skip_str(start, src, &src[start.byte..]);
+    Ok((TokenKind::Error(&src[start.byte..]), end))
+}
+
+/// Collects the errors found while lexing. When recovering, errors are recorded and lexing
+/// carries on, otherwise the first error aborts lexing.
+pub(crate) struct Errors {
+    pub(crate) recover: bool,
+    pub(crate) errors: Vec<LexError>,
+}
+
+impl Errors {
+    /// Report an error. Returns the error back if we aren't recovering from errors.
+    pub(crate) fn report(&mut self, error: LexError) -> Result<(), LexError> {
+        match self.recover {
+            true => {
+                self.errors.push(error);
+                Ok(())
+            },
+            false => Err(error),
+        }
+    }
+}
+
+/// The next thing in the source, as found by `lex_item`.
+pub(crate) enum Item<'s, S> {
+    /// A token other than a bracket.
+    Token(Token<'s, S>),
+    /// An opening bracket and the position after it.
+    Open(char, TextPos),
+    /// A closing bracket and the position after it.
+    Close(char, TextPos),
+    /// The end of the source.
+    End,
+}
+
+/// Lex the next item in the source starting at `pos`. Brackets are returned as individual
+/// `Open` and `Close` items rather than being matched up.
+pub(crate) fn lex_item<'s, S: Clone>(pos: TextPos,
+                                     src: &'s str,
+                                     config: &LexerConfig<'s, S>,
+                                     errors: &mut Errors) -> Result<Item<'s, S>, LexError>
+{
+    let (c, p) = match pos.next(src) {
+        Some(x) => x,
+        None => return Ok(Item::End),
+    };
+    if c.is_whitespace() {
+        let mut end = p;
+        while let Some((c, p)) = end.next(src) {
+            if !c.is_whitespace() {
+                break;
             }
+            end = p;
+        }
 
-            if is_symbol {
-                let token = Token {
-                    kind: TokenKind::Symbol(sym_prefix),
-                    start: pos,
-                };
-                tokens.push(token);
-                pos = sym_end;
-                continue 'main;
+        let token = Token {
+            kind: TokenKind::Whitespace(&src[pos.byte..end.byte]),
+            start: pos,
+            end,
+        };
+
+        return Ok(Item::Token(token));
+    }
+    let comment_end = match lex_comment(pos, src, config.comment_syntax()) {
+        Ok(comment_end) => comment_end,
+        Err(e) => {
+            errors.report(e)?;
+            Some(skip_str(pos, src, &src[pos.byte..]))
+        },
+    };
+    if let Some(end) = comment_end {
+        let token = Token {
+            kind: TokenKind::Comment(&src[pos.byte..end.byte]),
+            start: pos,
+            end,
+        };
+        return Ok(Item::Token(token));
+    }
+    if config.has_raw_strings() && config.is_quote('"') && c == 'r' {
+        let raw_string = match lex_raw_string(pos, src) {
+            Ok(raw_string) => raw_string.map(|(string, end)| (TokenKind::String(string), end)),
+            Err(e) => {
+                errors.report(e)?;
+                Some((TokenKind::Error(&src[pos.byte..]), skip_str(pos, src, &src[pos.byte..])))
+            },
+        };
+        if let Some((kind, end)) = raw_string {
+            let token = Token {
+                kind,
+                start: pos,
+                end,
             };
+            return Ok(Item::Token(token));
+        }
+    }
+    if config.is_ident_start(c) {
+        let mut end = p;
+        while let Some((c, p)) = end.next(src) {
+            if !config.is_ident_continue(c) {
+                break;
+            }
+            end = p;
+        }
+
+        let token = Token {
+            kind: TokenKind::Ident(&src[pos.byte..end.byte]),
+            start: pos,
+            end,
+        };
 
-            let new_p = match sym_end.next(src) {
-                Some((c, new_p)) => {
-                    if c.is_whitespace() ||
-                       c.is_xid_start() ||
-                       c.is_open_bracket() ||
-                       c.is_close_bracket() ||
-                       c == '\'' || c == '"' {
+        return Ok(Item::Token(token));
+    }
+    if config.has_numbers() && c.is_ascii_digit() {
+        let (kind, end) = match lex_number(pos, src, config) {
+            Ok((number, end)) => (TokenKind::Number(number), end),
+            Err(e) => {
+                errors.report(e)?;
+                let mut end = p;
+                while let Some((c, p)) = end.next(src) {
+                    if !(c.is_ascii_digit() || c == '_' || config.is_ident_continue(c)) {
                         break;
                     }
-                    new_p
-                },
-                _ => break,
-            };
-            sym_end = new_p;
-        }
-        return Err(LexError::UnexpectedChar {
-            c: c,
-            pos: pos,
-        });
+                    end = p;
+                }
+                (TokenKind::Error(&src[pos.byte..end.byte]), end)
+            },
+        };
+        let token = Token {
+            kind,
+            start: pos,
+            end,
+        };
+        return Ok(Item::Token(token));
+    }
+    if config.is_open_bracket(c) {
+        return Ok(Item::Open(c, p));
+    }
+    if config.is_close_bracket(c) {
+        return Ok(Item::Close(c, p));
+    }
+    if config.is_quote(c) {
+        let (kind, end) = lex_string(pos, src, config, errors)?;
+        let token = Token {
+            kind,
+            start: pos,
+            end,
+        };
+        return Ok(Item::Token(token));
     }
-}
 
-pub fn lex<'s>(src: &'s str, symbols: &[&'s str]) -> Result<TokensBuf<'s>, LexError<'s>> {
-    match validate_symbol_table(symbols) {
-        Ok(()) => (),
-        Err(e) => return Err(LexError::InvalidSymbolTable(e)),
+    let is_break = |c: char| {
+        c.is_whitespace() ||
+        config.is_ident_start(c) ||
+        config.is_open_bracket(c) ||
+        config.is_close_bracket(c) ||
+        config.is_quote(c)
     };
+    let symbol_table = config.symbol_table();
+    if let Some(index) = symbol_table.find(&src[pos.byte..], is_break) {
+        let end = skip_str(pos, src, symbol_table.symbols()[index]);
+        let token = Token {
+            kind: TokenKind::Symbol(&src[pos.byte..end.byte], symbol_table.values()[index].clone()),
+            start: pos,
+            end,
+        };
+        return Ok(Item::Token(token));
+    }
 
-    let pos = TextPos::start();
-    let sub = try!(sub_lex(pos, src, symbols));
-    match sub.terminator {
-        None => return Ok(sub.tokens),
-        Some((c, _)) => return Err(LexError::UnexpectedClosingBracket {
-            pos: sub.tokens.end,
-            c: c,
-        }),
+    errors.report(LexError::UnexpectedChar {
+        c,
+        pos,
+    })?;
+    let token = Token {
+        kind: TokenKind::Error(&src[pos.byte..p.byte]),
+        start: pos,
+        end: p,
     };
+    Ok(Item::Token(token))
 }
 
+/// Lex `src`, the source of `file`, into a token tree using the given symbol table. This
+/// validates and compiles the symbol table on every call, to lex many sources with the same table
+/// build a `LexerConfig` once and use its `lex` method instead.
+pub fn lex<'s>(file: FileId, src: &'s str, symbols: &[&'s str]) -> Result<TokensBuf<'s>, LexError> {
+    if let Err(e) = validate_symbol_table(symbols) {
+        return Err(LexError::InvalidSymbolTable(e));
+    }
+    LexerConfig::new(SymbolTable::compile(symbols)).lex(file, src)
+}
+
+/// Lex `src` into a token tree using the given symbol table, recovering from errors. If the symbol
+/// table is invalid the error is reported and the table is used anyway. See
+/// `LexerConfig::lex_recovering`.
+pub fn lex_recovering<'s>(file: FileId, src: &'s str, symbols: &[&'s str]) -> (TokensBuf<'s>, Vec<LexError>) {
+    let table_error = validate_symbol_table(symbols).err().map(LexError::InvalidSymbolTable);
+    let config = LexerConfig::new(SymbolTable::compile(symbols));
+    let (tokens, mut errors) = config.lex_recovering(file, src);
+    if let Some(e) = table_error {
+        errors.insert(0, e);
+    }
+    (tokens, errors)
+}
+
+pub(crate) fn lex_with_config<'s, S: Clone>(file: FileId, src: &'s str, config: &LexerConfig<'s, S>)
+    -> Result<TokensBuf<'s, S>, LexError>
+{
+    let mut lexer = Lexer::new(file, src, config);
+    let tokens = build_tree(&mut lexer, Err)?;
+    Ok(tokens)
+}
+
+pub(crate) fn lex_recovering_with_config<'s, S: Clone>(file: FileId,
+                                                      src: &'s str,
+                                                      config: &LexerConfig<'s, S>)
+    -> (TokensBuf<'s, S>, Vec<LexError>)
+{
+    let mut lexer = Lexer::recovering(file, src, config);
+    let mut errors = Vec::new();
+    let Ok(tokens) = build_tree(&mut lexer, |e| {
+        errors.push(e);
+        Ok::<(), Infallible>(())
+    });
+    (tokens, errors)
+}
+
+/// A bracket which has been opened but not yet closed.
+struct OpenBracket<'s, S> {
+    /// The opening bracket character.
+    c: char,
+    /// The position of the opening bracket.
+    pos: TextPos,
+    /// The position after the opening bracket.
+    inner_start: TextPos,
+    /// The tokens lexed so far inside the bracket.
+    tokens: Vec<Token<'s, S>>,
+}
+
+impl<'s, S> OpenBracket<'s, S> {
+    /// Close the bracket with `close`, producing a bracket token. `close_pos` is the position of
+    /// the closing bracket and `end` the position after it.
+    fn close(self, close: Option<char>, close_pos: TextPos, end: TextPos) -> Token<'s, S> {
+        Token {
+            kind: TokenKind::Bracket(self.c, TokensBuf {
+                tokens: self.tokens,
+                start: self.inner_start,
+                end: close_pos,
+            }, close),
+            start: self.pos,
+            end,
+        }
+    }
+}
+
+/// Collect the events from a `Lexer` into a token tree. Errors are passed to `on_error`, which
+/// decides whether to carry on.
+fn build_tree<'c, 's: 'c, S: Clone + 'c, E, F>(lexer: &mut Lexer<'c, 's, S>, on_error: F)
+    -> Result<TokensBuf<'s, S>, E>
+    where F: FnMut(LexError) -> Result<(), E>
+{
+    let start = lexer.pos();
+    let (tokens, _) = build_tokens(lexer, on_error)?;
+    Ok(TokensBuf {
+        tokens,
+        start,
+        end: lexer.pos(),
+    })
+}
+
+/// A closing bracket character, or `None` if it was missing, along with its span.
+pub(crate) type CloseBracket = (Option<char>, Span);
+
+/// Collect the events from a `Lexer` into a list of token trees. Stops at the end of the source
+/// or at a closing bracket which closes a bracket opened before the lexer was started, in which
+/// case that closing bracket and its span are returned as well.
+pub(crate) fn build_tokens<'c, 's: 'c, S: Clone + 'c, E, F>(lexer: &mut Lexer<'c, 's, S>,
+                                                             mut on_error: F)
+    -> Result<(Vec<Token<'s, S>>, Option<CloseBracket>), E>
+    where F: FnMut(LexError) -> Result<(), E>
+{
+    // Brackets are matched using an explicit stack, as in `write_tokens`.
+    let mut tokens = Vec::new();
+    let mut stack: Vec<OpenBracket<'s, S>> = Vec::new();
+    for event in lexer.by_ref() {
+        let token = match event {
+            Ok(FlatToken::Token(token)) => token,
+            Ok(FlatToken::Open(c, span)) => {
+                stack.push(OpenBracket {
+                    c,
+                    pos: span.start,
+                    inner_start: span.end,
+                    tokens: Vec::new(),
+                });
+                continue;
+            },
+            Ok(FlatToken::Close(c, span)) => {
+                match stack.pop() {
+                    Some(open) => open.close(c, span.start, span.end),
+                    None => return Ok((tokens, Some((c, span)))),
+                }
+            },
+            Err(e) => {
+                on_error(e)?;
+                continue;
+            },
+        };
+        match stack.last_mut() {
+            Some(open) => open.tokens.push(token),
+            None => tokens.push(token),
+        }
+    }
+    Ok((tokens, None))
+}
@@ -1,36 +1,68 @@
-#![feature(pattern)]
-#![feature(unicode)]
+#![no_std]
 
-extern crate unicode_width;
-extern crate unicode_xid;
-extern crate unicode_brackets;
+#[macro_use]
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
 
 mod span;
 mod token;
 mod tokens;
 mod symbol_table;
 mod lex;
+mod stream;
+mod relex;
+mod owned;
+mod config;
+mod diagnostic;
+mod lsp;
+mod line_index;
+mod source_map;
 
 pub use span::{TextPos, Span};
-pub use token::{TokenKind, Token};
+pub use token::{TokenKind, Token, StringLit, NumberLit};
 pub use tokens::{TokensBuf, TokensRef};
-pub use symbol_table::{InvalidSymbolTableError, validate_symbol_table};
-pub use lex::{LexError, lex};
+pub use symbol_table::{InvalidSymbolTableError, Collision, SymbolTable, MatchMode, validate_symbol_table};
+pub use symbol_table::{SymbolTableWarning, analyze_symbol_table};
+pub use lex::{LexError, lex, lex_recovering};
+pub use stream::{FlatToken, Lexer};
+pub use relex::Edit;
+pub use owned::{ArcStr, OwnedTokens, OwnedToken, OwnedTokenKind, OwnedStringLit, OwnedNumberLit};
+pub use config::{LexerConfig, CommentSyntax, DEFAULT_MAX_DEPTH};
+pub use diagnostic::{Diagnostic, Label};
+pub use lsp::{LspPosition, LspRange, PositionEncoding};
+pub use line_index::LineIndex;
+pub use source_map::{FileId, SourceMap};
 
 #[cfg(test)]
 mod test {
-    use token::{Token, TokenKind};
-    use tokens::TokensBuf;
-    use span::TextPos;
-    use lex::{LexError, lex};
+    use crate::token::{Token, TokenKind, StringLit, NumberLit};
+    use crate::tokens::TokensBuf;
+    use crate::span::{TextPos, Span};
+    use crate::symbol_table::{InvalidSymbolTableError, SymbolTable};
+    use crate::lex::{LexError, lex, lex_recovering};
+    use crate::stream::{FlatToken, Lexer};
+    use crate::relex::Edit;
+    use crate::owned::{ArcStr, OwnedTokenKind};
+    use crate::config::{LexerConfig, CommentSyntax, DEFAULT_MAX_DEPTH};
+    use crate::lsp::{LspPosition, LspRange, PositionEncoding};
+    use crate::line_index::LineIndex;
+    use crate::source_map::{FileId, SourceMap};
 
+    use std::string::{String, ToString};
+    use std::vec::Vec;
     use std::borrow::Cow;
+    use std::sync::Arc;
 
     fn tp(p: usize) -> TextPos {
         TextPos {
             col: p,
             line: 0,
             byte: p,
+            byte_col: p,
+            char_col: p,
+            utf16_col: p,
+            file: FileId(0),
         }
     }
 
@@ -41,15 +73,17 @@ mod test {
             "$%^",
         ];
         let src = r#"()[{}] "wow\"\t\n\x23""floo"  !@#$%^hello_123"\u{394}""#;
-        let tokens_buf = lex(src, &symbols).unwrap();
+        let tokens_buf = lex(FileId(0), src, &symbols).unwrap();
         assert_eq!(tokens_buf, TokensBuf {
             tokens: vec![
                 Token {
                     kind: TokenKind::Bracket('(', TokensBuf {
                         tokens: vec![],
+                        start: tp(1),
                         end: tp(1),
-                    }),
+                    }, Some(')')),
                     start: tp(0),
+                    end: tp(2),
                 },
                 Token {
                     kind: TokenKind::Bracket('[', TokensBuf {
@@ -57,119 +91,911 @@ mod test {
                             Token {
                                 kind: TokenKind::Bracket('{', TokensBuf {
                                     tokens: vec![],
+                                    start: tp(4),
                                     end: tp(4),
-                                }),
+                                }, Some('}')),
                                 start: tp(3),
+                                end: tp(5),
                             },
                         ],
+                        start: tp(3),
                         end: tp(5),
-                    }),
+                    }, Some(']')),
                     start: tp(2),
+                    end: tp(6),
                 },
                 Token {
                     kind: TokenKind::Whitespace(" "),
                     start: tp(6),
+                    end: tp(7),
                 },
                 Token {
-                    kind: TokenKind::String(Cow::Owned(String::from("wow\"\t\n#"))),
+                    kind: TokenKind::String(StringLit {
+                        quote: '"',
+                        hashes: None,
+                        raw: r#"wow\"\t\n\x23"#,
+                        value: Cow::Owned(String::from("wow\"\t\n#")),
+                    }),
                     start: tp(7),
+                    end: tp(22),
                 },
                 Token {
-                    kind: TokenKind::String(Cow::Borrowed("floo")),
+                    kind: TokenKind::String(StringLit {
+                        quote: '"',
+                        hashes: None,
+                        raw: "floo",
+                        value: Cow::Borrowed("floo"),
+                    }),
                     start: tp(22),
+                    end: tp(28),
                 },
                 Token {
                     kind: TokenKind::Whitespace("  "),
                     start: tp(28),
+                    end: tp(30),
                 },
                 Token {
-                    kind: TokenKind::Symbol("!@#"),
+                    kind: TokenKind::Symbol("!@#", 0),
                     start: tp(30),
+                    end: tp(33),
                 },
                 Token {
-                    kind: TokenKind::Symbol("$%^"),
+                    kind: TokenKind::Symbol("$%^", 1),
                     start: tp(33),
+                    end: tp(36),
                 },
                 Token {
                     kind: TokenKind::Ident("hello_123"),
                     start: tp(36),
+                    end: tp(45),
                 },
                 Token {
-                    kind: TokenKind::String(Cow::Owned(String::from("Δ"))),
+                    kind: TokenKind::String(StringLit {
+                        quote: '"',
+                        hashes: None,
+                        raw: r"\u{394}",
+                        value: Cow::Owned(String::from("Δ")),
+                    }),
                     start: tp(45),
+                    end: tp(54),
                 }
             ],
+            start: tp(0),
             end: tp(54),
         });
     }
 
+    #[test]
+    fn test_quote_style() {
+        let tokens_buf = lex(FileId(0), r#"'a' "a" '\''"#, &[]).unwrap();
+        let strings: Vec<_> = tokens_buf.tokens.iter().filter_map(|token| match token.kind {
+            TokenKind::String(ref string) => Some((string.quote, string.raw, string.value.clone())),
+            _ => None,
+        }).collect();
+        assert_eq!(strings, vec![
+            ('\'', "a", Cow::Borrowed("a")),
+            ('"', "a", Cow::Borrowed("a")),
+            ('\'', r"\'", Cow::Owned(String::from("'"))),
+        ]);
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let src = r####"r"C:\dir" r##"a "# b"## r#x"####;
+        let tokens_buf = lex(FileId(0), src, &["#"]).unwrap();
+        let kinds: Vec<_> = tokens_buf.tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::String(StringLit {
+                quote: '"',
+                hashes: Some(0),
+                raw: r"C:\dir",
+                value: Cow::Borrowed(r"C:\dir"),
+            }),
+            TokenKind::Whitespace(" "),
+            TokenKind::String(StringLit {
+                quote: '"',
+                hashes: Some(2),
+                raw: r##"a "# b"##,
+                value: Cow::Borrowed(r##"a "# b"##),
+            }),
+            TokenKind::Whitespace(" "),
+            TokenKind::Ident("r"),
+            TokenKind::Symbol("#", 0),
+            TokenKind::Ident("x"),
+        ]);
+
+        let src = r##"r#"abc""##;
+        let err = lex(FileId(0), &src[..src.len() - 1], &[]).unwrap_err();
+        assert_eq!(err, LexError::UnclosedRawString {
+            start_pos: tp(0),
+            hashes: 1,
+        });
+
+        // A multi-byte character straight after too few closing hashes.
+        let err = lex(FileId(0), "r##\"a\"#\u{e9}", &[]).unwrap_err();
+        assert_eq!(err, LexError::UnclosedRawString {
+            start_pos: tp(0),
+            hashes: 2,
+        });
+        let tokens_buf = lex(FileId(0), "r##\"a\"#\u{e9}\"##", &[]).unwrap();
+        match tokens_buf.tokens[0].kind {
+            TokenKind::String(ref string) => assert_eq!(string.raw, "a\"#\u{e9}"),
+            ref kind => panic!("unexpected token {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_numbers() {
+        let src = "0 1_000 0xff_u8 0o17 0b1010 1.5e-3f64 2E10 3.x";
+        let tokens_buf = lex(FileId(0), src, &["."]).unwrap();
+        let numbers: Vec<_> = tokens_buf.tokens.iter().filter_map(|token| match token.kind {
+            TokenKind::Number(ref number) => Some((token.start.byte, number.clone())),
+            _ => None,
+        }).collect();
+        let number = |raw, radix, integer, fraction, exponent, suffix| NumberLit {
+            raw,
+            radix,
+            integer,
+            fraction,
+            exponent,
+            suffix,
+        };
+        assert_eq!(numbers, vec![
+            (0, number("0", 10, "0", None, None, None)),
+            (2, number("1_000", 10, "1_000", None, None, None)),
+            (8, number("0xff_u8", 16, "ff_", None, None, Some("u8"))),
+            (16, number("0o17", 8, "17", None, None, None)),
+            (21, number("0b1010", 2, "1010", None, None, None)),
+            (28, number("1.5e-3f64", 10, "1", Some("5"), Some("-3"), Some("f64"))),
+            (38, number("2E10", 10, "2", None, Some("10"), None)),
+            (43, number("3", 10, "3", None, None, None)),
+        ]);
+        assert_eq!(tokens_buf.tokens.last().unwrap().kind, TokenKind::Ident("x"));
+    }
+
+    #[test]
+    fn test_comments() {
+        let comments = CommentSyntax {
+            line: vec!["//", "#"],
+            block: vec![("/*", "*/")],
+            nested: true,
+        };
+        let src = "a // b\n/* c /* d */ e */#f";
+        let config = LexerConfig::new(SymbolTable::new(&[]).unwrap()).comments(comments.clone());
+        let tokens_buf = config.lex(FileId(0), src).unwrap();
+        let kinds: Vec<_> = tokens_buf.tokens.iter().map(|t| (t.start, t.kind.clone())).collect();
+        assert_eq!(kinds, vec![
+            (tp(0), TokenKind::Ident("a")),
+            (tp(1), TokenKind::Whitespace(" ")),
+            (tp(2), TokenKind::Comment("// b")),
+            (tp(6), TokenKind::Whitespace("\n")),
+            (TextPos { line: 1, byte: 7, ..tp(0) }, TokenKind::Comment("/* c /* d */ e */")),
+            (TextPos { line: 1, byte: 24, ..tp(17) }, TokenKind::Comment("#f")),
+        ]);
+
+        // A lone `\r` ends a line comment too.
+        let src = "x\r// c\ry";
+        let tokens_buf = config.lex(FileId(0), src).unwrap();
+        let kinds: Vec<_> = tokens_buf.tokens.iter().map(|t| (t.start, t.kind.clone())).collect();
+        assert_eq!(kinds, vec![
+            (tp(0), TokenKind::Ident("x")),
+            (tp(1), TokenKind::Whitespace("\r")),
+            (TextPos { line: 1, byte: 2, ..tp(0) }, TokenKind::Comment("// c")),
+            (TextPos { line: 1, byte: 6, ..tp(4) }, TokenKind::Whitespace("\r")),
+            (TextPos { line: 2, byte: 7, ..tp(0) }, TokenKind::Ident("y")),
+        ]);
+
+        let src = "/* a /* b */";
+        let err = config.lex(FileId(0), src).unwrap_err();
+        assert_eq!(err, LexError::UnclosedComment {
+            start_pos: tp(0),
+        });
+
+        let flat = CommentSyntax {
+            nested: false,
+            .. comments
+        };
+        let tokens_buf = LexerConfig::new(SymbolTable::new(&[]).unwrap()).comments(flat).lex(FileId(0), src).unwrap();
+        assert_eq!(tokens_buf.tokens[0].kind, TokenKind::Comment(src));
+    }
+
+    #[test]
+    fn test_config() {
+        let config = LexerConfig::new(SymbolTable::new(&["(", ")", "1"]).unwrap())
+            .quotes(&['`'])
+            .escapes(false)
+            .numbers(false)
+            .brackets(&[('[', ']')]);
+        let src = r"`a\b`(1)[]";
+        let tokens_buf = config.lex(FileId(0), src).unwrap();
+        let kinds: Vec<_> = tokens_buf.tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::String(StringLit {
+                quote: '`',
+                hashes: None,
+                raw: r"a\b",
+                value: Cow::Borrowed(r"a\b"),
+            }),
+            TokenKind::Symbol("(", 0),
+            TokenKind::Symbol("1", 2),
+            TokenKind::Symbol(")", 1),
+            TokenKind::Bracket('[', TokensBuf {
+                tokens: vec![],
+                start: tp(9),
+                end: tp(9),
+            }, Some(']')),
+        ]);
+
+        // Raw strings are delimited by `"`, so they go along with it.
+        let err = config.lex(FileId(0), r#"r"a""#).unwrap_err();
+        assert_eq!(err, LexError::UnexpectedChar {
+            pos: tp(1),
+            c: '"',
+        });
+
+        fn is_upper(c: char) -> bool { c.is_uppercase() }
+        let config = LexerConfig::new(SymbolTable::new(&[]).unwrap()).idents(is_upper, is_upper);
+        let err = config.lex(FileId(0), "AB c").unwrap_err();
+        assert_eq!(err, LexError::UnexpectedChar {
+            pos: tp(3),
+            c: 'c',
+        });
+    }
+
+    #[test]
+    fn test_symbol_values() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        enum Op {
+            Plus,
+            Times,
+        }
+
+        let table = SymbolTable::with_values(&[("+", Op::Plus), ("*", Op::Times)]).unwrap();
+        let tokens_buf = LexerConfig::new(table).lex(FileId(0), "a+b*c").unwrap();
+        let ops: Vec<_> = tokens_buf.tokens.iter().filter_map(|token| match token.kind {
+            TokenKind::Symbol(_, op) => Some(op),
+            _ => None,
+        }).collect();
+        assert_eq!(ops, vec![Op::Plus, Op::Times]);
+    }
+
+    #[test]
+    fn test_longest_match() {
+        let table = SymbolTable::longest_match(&["<", "<<", "<=", "<<=", "="]).unwrap();
+        let tokens_buf = LexerConfig::new(table).lex(FileId(0), "a<<=b<<c< =d").unwrap();
+        let symbols: Vec<_> = tokens_buf.tokens.iter().filter_map(|token| match token.kind {
+            TokenKind::Symbol(s, _) => Some(s),
+            _ => None,
+        }).collect();
+        assert_eq!(symbols, vec!["<<=", "<<", "<", "="]);
+
+        let err = SymbolTable::new(&["<", "<<", "<=", "<<=", "="]).unwrap_err();
+        assert_eq!(err, InvalidSymbolTableError::Ambiguous {
+            text: String::from("<<"),
+            first: vec![String::from("<<")],
+            second: vec![String::from("<"), String::from("<")],
+        });
+    }
+
+    #[test]
+    fn test_stream() {
+        let config = LexerConfig::new(SymbolTable::new(&["+"]).unwrap());
+        let events: Vec<_> = Lexer::new(FileId(0), "a(+)", &config).collect();
+        assert_eq!(events, vec![
+            Ok(FlatToken::Token(Token {
+                kind: TokenKind::Ident("a"),
+                start: tp(0),
+                end: tp(1),
+            })),
+            Ok(FlatToken::Open('(', Span { start: tp(1), end: tp(2) })),
+            Ok(FlatToken::Token(Token {
+                kind: TokenKind::Symbol("+", 0),
+                start: tp(2),
+                end: tp(3),
+            })),
+            Ok(FlatToken::Close(Some(')'), Span { start: tp(3), end: tp(4) })),
+        ]);
+
+        let events: Vec<_> = Lexer::new(FileId(0), "(a]b", &config).collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2], Err(LexError::InvalidClosingBracket {
+            open_pos: tp(0),
+            close_pos: tp(2),
+        }));
+
+        let events: Vec<_> = Lexer::recovering(FileId(0), "(a]b[", &config).collect();
+        assert_eq!(&events[2..], &[
+            Err(LexError::InvalidClosingBracket {
+                open_pos: tp(0),
+                close_pos: tp(2),
+            }),
+            Ok(FlatToken::Close(Some(']'), Span { start: tp(2), end: tp(3) })),
+            Ok(FlatToken::Token(Token {
+                kind: TokenKind::Ident("b"),
+                start: tp(3),
+                end: tp(4),
+            })),
+            Ok(FlatToken::Open('[', Span { start: tp(4), end: tp(5) })),
+            Err(LexError::UnclosedBracket {
+                open_pos: tp(4),
+            }),
+            Ok(FlatToken::Close(None, Span { start: tp(5), end: tp(5) })),
+        ][..]);
+
+        // Streaming only needs to remember the open brackets, so a long input is never held in
+        // memory as a tree.
+        let src = "(a + b) ".repeat(10000);
+        let mut lexer = Lexer::new(FileId(0), &src, &config);
+        let mut count = 0;
+        while let Some(event) = lexer.next() {
+            event.unwrap();
+            assert!(lexer.depth() <= 1);
+            count += 1;
+        }
+        assert_eq!(count, 8 * 10000);
+        assert_eq!(lexer.pos().byte, src.len());
+    }
+
+    #[test]
+    fn test_relex() {
+        let comments = CommentSyntax {
+            line: vec!["//"],
+            block: vec![("/*", "*/")],
+            nested: false,
+        };
+        let config = LexerConfig::new(SymbolTable::new(&["+", "=", ";"]).unwrap()).comments(comments);
+        let old_src = "let a = (b + [c\n + \"d\"]);\nlet e = (1.5e3 + f) + r\"g\"; // h\n";
+
+        let check = |range: std::ops::Range<usize>, text: &str| {
+            let src = format!("{}{}{}", &old_src[..range.start], text, &old_src[range.end..]);
+            // Use a file other than the default so that a relex which loses track of the file
+            // is caught.
+            let old = config.lex(FileId(3), old_src).unwrap();
+            let edit = Edit {
+                range,
+                text,
+            };
+            assert_eq!(config.relex(&old, old_src, &edit, &src), config.lex(FileId(3), &src));
+        };
+
+        // Edits inside brackets, including ones which change line numbers.
+        check(14..15, "cc");
+        check(14..15, "x\ny");
+        check(14..16, "");
+        check(9..9, "q + ");
+        check(18..21, "'d'");
+        check(36..39, "2");
+        // Edits which move or remove the closing bracket.
+        check(15..15, ")");
+        check(15..15, "] + [");
+        check(13..13, "/* ");
+        check(14..14, "\"");
+        check(22..23, "");
+        // Edits outside any bracket.
+        check(0..3, "var");
+        check(57..57, " +");
+        check(0..old_src.len(), "");
+
+        // Every single character deletion and insertion.
+        for i in 0..old_src.len() {
+            check(i..i + 1, "");
+            check(i..i, "(");
+            check(i..i, "+");
+        }
+        // A tree lexed from a copy of the old source, rather than the same string.
+        let copy = String::from(old_src);
+        let old = config.lex(FileId(0), &copy).unwrap();
+        let src = format!("{}x{}", &old_src[..14], &old_src[15..]);
+        let edit = Edit {
+            range: 14..15,
+            text: "x",
+        };
+        assert_eq!(config.relex(&old, old_src, &edit, &src), config.lex(FileId(0), &src));
+
+        // An edit which turns an opening bracket into the start of a comment.
+        let comments = CommentSyntax {
+            line: vec![],
+            block: vec![("(*", "*)")],
+            nested: false,
+        };
+        let config = LexerConfig::new(SymbolTable::new(&["*"]).unwrap()).comments(comments);
+        let old = config.lex(FileId(0), "f(a)").unwrap();
+        let edit = Edit {
+            range: 2..2,
+            text: "*",
+        };
+        let err = config.relex(&old, "f(a)", &edit, "f(*a)").unwrap_err();
+        assert_eq!(err, LexError::UnclosedComment {
+            start_pos: tp(1),
+        });
+    }
+
+    #[test]
+    fn test_to_source() {
+        let comments = CommentSyntax {
+            line: vec!["//"],
+            block: vec![("/*", "*/")],
+            nested: true,
+        };
+        let config = LexerConfig::new(SymbolTable::new(&["+", "=", ";", "."]).unwrap()).comments(comments);
+        let srcs = [
+            "",
+            "let a = (b + [c\n\t+ \"d\\n\\u{394}\"]);\r\n",
+            "x = r##\"a \"# b\"## + 'c' + 0xFF_u8 + 1.5E-3f32 + 2e+7; // done",
+            "/* a /* b */ */ {}()[]",
+            // Broken input, lexed with recovery.
+            "a # (b] ) 0b2 '\\q' [c",
+            "(( \"abc",
+            "1e /* x",
+        ];
+        for src in srcs.iter() {
+            let (tokens_buf, _) = config.lex_recovering(FileId(0), src);
+            assert_eq!(tokens_buf.to_source(), *src);
+            assert_eq!(tokens_buf.to_string(), *src);
+            let pieces: String = tokens_buf.tokens.iter().map(|t| t.to_source()).collect();
+            assert_eq!(pieces, *src);
+        }
+
+        let src = "f(a, b)";
+        let tokens_buf = lex(FileId(0), src, &[","]).unwrap();
+        assert_eq!(tokens_buf.borrow().range_from(1).to_source(), "(a, b)");
+        match tokens_buf.tokens[1].kind {
+            TokenKind::Bracket(_, ref args, _) => assert_eq!(args.to_string(), "a, b"),
+            _ => panic!(),
+        }
+
+        let depth = 100000;
+        let mut src = String::new();
+        for _ in 0..depth {
+            src.push('(');
+        }
+        let (mut tokens_buf, _) = LexerConfig::new(SymbolTable::new(&[]).unwrap()).max_depth(None).lex_recovering(FileId(0), &src);
+        assert_eq!(tokens_buf.to_source(), src);
+        while let Some(token) = tokens_buf.tokens.pop() {
+            tokens_buf = match token.kind {
+                TokenKind::Bracket(_, inner, _) => inner,
+                _ => panic!(),
+            };
+        }
+    }
+
+    #[test]
+    fn test_owned() {
+        let src: Arc<str> = Arc::from("f(a, \"b\\n\", 'c', 1.5e3) // d");
+        let comments = CommentSyntax {
+            line: vec!["//"],
+            .. CommentSyntax::default()
+        };
+        let config = LexerConfig::new(SymbolTable::new(&[","]).unwrap()).comments(comments);
+        let tokens_buf = config.lex(FileId(0), &src).unwrap();
+        let owned = tokens_buf.clone().into_owned(&src);
+        assert_eq!(owned.to_tokens_buf(), tokens_buf);
+        assert_eq!(owned.to_string(), &src[..]);
+
+        // Text is shared with the source rather than copied.
+        match owned.tokens[0].kind {
+            OwnedTokenKind::Ident(ref s) => {
+                assert_eq!(*s, "f");
+                assert!(Arc::ptr_eq(s.source(), &src));
+            },
+            _ => panic!(),
+        }
+
+        // The tree outlives the source and can be sent to another thread.
+        drop(tokens_buf);
+        let weak = Arc::downgrade(&src);
+        drop(src);
+        let handle = std::thread::spawn(move || owned.to_tokens_buf().to_source());
+        assert_eq!(handle.join().unwrap(), "f(a, \"b\\n\", 'c', 1.5e3) // d");
+        assert!(weak.upgrade().is_none());
+
+        // Tokens lexed from some other string get their text copied.
+        let other = String::from("x");
+        let tokens_buf = lex(FileId(0), &other, &[]).unwrap();
+        let owned = tokens_buf.into_owned(&Arc::from(""));
+        assert_eq!(owned.tokens[0].kind, OwnedTokenKind::Ident(ArcStr::from("x")));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use crate::owned::OwnedTokens;
+        use crate::symbol_table::MatchMode;
+
+        let src = "f(x, 'y')";
+        let tokens_buf = lex(FileId(0), src, &[","]).unwrap();
+        let json = serde_json::to_string(&tokens_buf.borrow().range(0, 1)).unwrap();
+        assert_eq!(json, r#"{"tokens":[{"start":{"col":0,"line":0,"byte":0,"byte_col":0,"char_col":0,"utf16_col":0,"file":0},"end":{"col":1,"line":0,"byte":1,"byte_col":1,"char_col":1,"utf16_col":1,"file":0},"kind":{"Ident":"f"}}],"start":{"col":0,"line":0,"byte":0,"byte_col":0,"char_col":0,"utf16_col":0,"file":0},"end":{"col":1,"line":0,"byte":1,"byte_col":1,"char_col":1,"utf16_col":1,"file":0}}"#);
+
+        let json = serde_json::to_string(&tokens_buf).unwrap();
+        let de: TokensBuf = serde_json::from_str(&json).unwrap();
+        assert_eq!(de, tokens_buf);
+
+        // Text containing JSON escapes can't be borrowed, so deserialize it as `OwnedTokens`
+        // instead. The JSON is the same either way.
+        let src = "a\n\"b\\n\"";
+        let tokens_buf = lex(FileId(0), src, &[]).unwrap();
+        let json = serde_json::to_string(&tokens_buf).unwrap();
+        let owned: OwnedTokens = serde_json::from_str(&json).unwrap();
+        assert_eq!(owned.to_tokens_buf(), tokens_buf);
+        assert_eq!(serde_json::to_string(&owned).unwrap(), json);
+
+        let err = lex(FileId(0), "(]", &[]).unwrap_err();
+        let json = serde_json::to_string(&err).unwrap();
+        assert_eq!(json, r#"{"InvalidClosingBracket":{"open_pos":{"col":0,"line":0,"byte":0,"byte_col":0,"char_col":0,"utf16_col":0,"file":0},"close_pos":{"col":1,"line":0,"byte":1,"byte_col":1,"char_col":1,"utf16_col":1,"file":0}}}"#);
+        assert_eq!(serde_json::from_str::<LexError>(&json).unwrap(), err);
+
+        let table = SymbolTable::longest_match(&["<", "<<"]).unwrap();
+        let json = serde_json::to_string(&table).unwrap();
+        assert_eq!(json, r#"{"symbols":["<","<<"],"values":[0,1],"mode":"Longest"}"#);
+        let table: SymbolTable = serde_json::from_str(&json).unwrap();
+        assert_eq!(table.mode(), MatchMode::Longest);
+        assert!(serde_json::from_str::<SymbolTable>(r#"{"symbols":["<","<<"],"values":[0,1],"mode":"Unique"}"#).is_err());
+    }
+
+    #[test]
+    fn test_recovery() {
+        let src = "a # (b] ) 0b2 '\\q' [c";
+        let (tokens_buf, errors) = lex_recovering(FileId(0), src, &[]);
+        assert_eq!(errors, vec![
+            LexError::UnexpectedChar {
+                pos: tp(2),
+                c: '#',
+            },
+            LexError::InvalidClosingBracket {
+                open_pos: tp(4),
+                close_pos: tp(6),
+            },
+            LexError::UnexpectedClosingBracket {
+                pos: tp(8),
+                c: ')',
+            },
+            LexError::InvalidDigit {
+                c: '2',
+                radix: 2,
+                pos: tp(12),
+            },
+            LexError::InvalidEscapeChar {
+                c: 'q',
+                pos: tp(16),
+            },
+            LexError::UnclosedBracket {
+                open_pos: tp(19),
+            },
+        ]);
+        let kinds: Vec<_> = tokens_buf.tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::Ident("a"),
+            TokenKind::Whitespace(" "),
+            TokenKind::Error("#"),
+            TokenKind::Whitespace(" "),
+            TokenKind::Bracket('(', TokensBuf {
+                tokens: vec![
+                    Token {
+                        kind: TokenKind::Ident("b"),
+                        start: tp(5),
+                        end: tp(6),
+                    },
+                ],
+                start: tp(5),
+                end: tp(6),
+            }, Some(']')),
+            TokenKind::Whitespace(" "),
+            TokenKind::Error(")"),
+            TokenKind::Whitespace(" "),
+            TokenKind::Error("0b2"),
+            TokenKind::Whitespace(" "),
+            TokenKind::String(StringLit {
+                quote: '\'',
+                hashes: None,
+                raw: r"\q",
+                value: Cow::Owned(String::from("\u{fffd}")),
+            }),
+            TokenKind::Whitespace(" "),
+            TokenKind::Bracket('[', TokensBuf {
+                tokens: vec![
+                    Token {
+                        kind: TokenKind::Ident("c"),
+                        start: tp(20),
+                        end: tp(21),
+                    },
+                ],
+                start: tp(20),
+                end: tp(21),
+            }, None),
+        ]);
+
+        let (tokens_buf, errors) = lex_recovering(FileId(0), "x \"abc", &[]);
+        assert_eq!(errors, vec![LexError::UnclosedString { start_pos: tp(2) }]);
+        assert_eq!(tokens_buf.tokens[2].kind, TokenKind::Error("\"abc"));
+        assert_eq!(tokens_buf.end, tp(6));
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let depth = 100_000;
+        let mut src = String::new();
+        for _ in 0..depth {
+            src.push('(');
+        }
+        let err = lex(FileId(0), &src, &[]).unwrap_err();
+        assert_eq!(err, LexError::NestingTooDeep {
+            pos: tp(DEFAULT_MAX_DEPTH),
+        });
+
+        for _ in 0..depth {
+            src.push(')');
+        }
+        let config = LexerConfig::new(SymbolTable::new(&[]).unwrap()).max_depth(None);
+
+        // Printing and converting to and from an owned tree don't recurse either.
+        let tokens_buf = config.lex(FileId(0), &src).unwrap();
+        assert_eq!(tokens_buf.to_source(), src);
+        let mut owned = tokens_buf.into_owned(&Arc::from(&src[..]));
+        assert_eq!(owned.to_string(), src);
+        let mut tokens_buf = owned.to_tokens_buf();
+        assert_eq!(tokens_buf.to_source(), src);
+
+        // Take the trees apart one level at a time since dropping them in one go would overflow
+        // the stack.
+        let mut levels = 0;
+        while let Some(token) = tokens_buf.tokens.pop() {
+            tokens_buf = match token.kind {
+                TokenKind::Bracket('(', inner, _) => inner,
+                _ => panic!(),
+            };
+            levels += 1;
+        }
+        assert_eq!(levels, depth);
+        let mut levels = 0;
+        while let Some(token) = owned.tokens.pop() {
+            owned = match token.kind {
+                OwnedTokenKind::Bracket('(', inner, _) => inner,
+                _ => panic!(),
+            };
+            levels += 1;
+        }
+        assert_eq!(levels, depth);
+    }
+
+    #[test]
+    fn test_spans() {
+        let src = "f(a, b) [c]";
+        let tokens_buf = lex(FileId(0), src, &[","]).unwrap();
+        let tokens = tokens_buf.borrow();
+        let span = |start, end| Span {
+            start: tp(start),
+            end: tp(end),
+        };
+
+        let call = &tokens.tokens[1];
+        assert_eq!(call.span(), span(1, 7));
+        assert_eq!(call.open_span(), Some(span(1, 2)));
+        assert_eq!(call.close_span(), Some(span(6, 7)));
+        assert_eq!(tokens.tokens[0].open_span(), None);
+
+        assert_eq!(tokens.range(0, 2).span(), span(0, 7));
+        assert_eq!(tokens.range(1, 1).span(), span(1, 1));
+        assert_eq!(tokens.range_from(2).span(), span(7, 11));
+        let (l, r) = tokens.split_around(2);
+        assert_eq!((l.span(), r.span()), (span(0, 7), span(8, 11)));
+
+        let args = match call.kind {
+            TokenKind::Bracket(_, ref args, _) => args.borrow(),
+            _ => panic!(),
+        };
+        assert_eq!(args.span(), span(2, 6));
+        assert_eq!(args.range_from(2).trim_whitespace().span(), span(5, 6));
+
+        let (tokens_buf, _) = lex_recovering(FileId(0), "(a", &[]);
+        let bracket = &tokens_buf.tokens[0];
+        assert_eq!(bracket.span(), span(0, 2));
+        assert_eq!(bracket.close_span(), Some(span(2, 2)));
+    }
+
+    #[test]
+    fn test_lsp_positions() {
+        let src = "\"é😀\" b\r\nc";
+        let tokens = lex(FileId(0), src, &[]).unwrap();
+        let string_end = tokens.tokens[0].end;
+        assert_eq!(string_end, TextPos {
+            col: 5,
+            line: 0,
+            byte: 8,
+            byte_col: 8,
+            char_col: 4,
+            utf16_col: 5,
+            file: FileId(0),
+        });
+        let lsp = |line, character| LspPosition { line, character };
+        assert_eq!(string_end.to_lsp(PositionEncoding::Utf8), lsp(0, 8));
+        assert_eq!(string_end.to_lsp(PositionEncoding::Utf16), lsp(0, 5));
+        assert_eq!(string_end.to_lsp(PositionEncoding::Utf32), lsp(0, 4));
+
+        let from_lsp = |line, character| TextPos::from_lsp(FileId(0), src, lsp(line, character), PositionEncoding::Utf16);
+        assert_eq!(from_lsp(0, 5), Some(string_end));
+        assert_eq!(from_lsp(0, 3).map(|p| p.byte), Some(3));
+        assert_eq!(from_lsp(0, 100).map(|p| p.byte), Some(10));
+        assert_eq!(from_lsp(2, 0), None);
+
+        let c = &tokens.tokens[4];
+        assert_eq!(c.kind, TokenKind::Ident("c"));
+        let range = c.span().to_lsp(PositionEncoding::Utf16);
+        assert_eq!(range, LspRange { start: lsp(1, 0), end: lsp(1, 1) });
+        assert_eq!(Span::from_lsp(FileId(0), src, range, PositionEncoding::Utf16), Some(c.span()));
+    }
+
+    #[test]
+    fn test_line_index() {
+        let src = "ab\r\nc\u{3b4}\u{1f600}\rd\n\n\te";
+        let index = LineIndex::new(FileId(0), src);
+        assert_eq!(index.line_count(), 5);
+        let lines: Vec<_> = (0..5).map(|line| index.line(line).unwrap()).collect();
+        assert_eq!(lines, ["ab", "c\u{3b4}\u{1f600}", "d", "", "\te"]);
+        assert_eq!(index.line(5), None);
+
+        // Every position should agree with walking the source from the start.
+        let mut expected = TextPos::start();
+        loop {
+            assert_eq!(index.pos(expected.byte), Some(expected));
+            expected = match expected.next(src) {
+                Some((_, p)) => p,
+                None => break,
+            };
+        }
+        assert_eq!(index.pos(6), None);
+        assert_eq!(index.pos(src.len() + 1), None);
+
+        let line_pos = |line, column| index.line_pos(line, column, PositionEncoding::Utf16).map(|p| p.byte);
+        assert_eq!(line_pos(0, 1), Some(1));
+        assert_eq!(line_pos(0, 5), Some(2));
+        assert_eq!(line_pos(1, 2), Some(7));
+        assert_eq!(line_pos(1, 3), Some(7));
+        assert_eq!(line_pos(1, 4), Some(11));
+        assert_eq!(line_pos(1, 9), Some(11));
+        assert_eq!(line_pos(4, 2), Some(src.len()));
+        assert_eq!(line_pos(5, 0), None);
+        let lsp = LspPosition { line: 1, character: 4 };
+        assert_eq!(index.from_lsp(lsp, PositionEncoding::Utf16), TextPos::from_lsp(FileId(0), src, lsp, PositionEncoding::Utf16));
+    }
+
+    #[test]
+    fn test_source_map() {
+        let mut files = SourceMap::new();
+        let a = files.add_file("a.malk", "f(x)");
+        let b = files.add_file("b.malk", "g\n  (y]");
+        assert_eq!(files.files().collect::<Vec<_>>(), [a, b]);
+        assert_eq!(files.name(b), "b.malk");
+
+        let tokens = lex(a, files.src(a), &[]).unwrap();
+        assert!(tokens.tokens.iter().all(|token| token.span().file() == a));
+        let tokens = lex(FileId(3), "a(b)", &[]).unwrap();
+        assert_eq!(tokens.borrow().span().file(), FileId(3));
+        assert_eq!(tokens.tokens[1].span().file(), FileId(3));
+        let err = lex(b, files.src(b), &[]).unwrap_err();
+        assert_eq!(err.pos().map(|pos| pos.file), Some(b));
+        assert_eq!(files.line_index(b).pos(6), err.pos());
+        assert_eq!(files.render(&err), "\
+error: mismatched closing bracket
+ --> b.malk:2:5
+  |
+2 |   (y]
+  |     ^ does not match the opening bracket
+  |   - opening bracket
+");
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = lex(FileId(0), "a\n  #", &[]).unwrap_err();
+        assert_eq!(err.to_string(), "2:3: unexpected character '#'");
+
+        let err = lex(FileId(0), r##"r#"abc"##, &[]).unwrap_err();
+        assert_eq!(err.to_string(), "1:1: unclosed raw string literal, expected a closing `\"#`");
+
+        let err = lex(FileId(0), "", &["+", "+-", "-"]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid symbol table: the symbol sequences `+-` and `+` `-` both spell out `+-`");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_std_error() {
+        use std::boxed::Box;
+        use std::error::Error;
+
+        let err = lex(FileId(0), "", &["+", "+-", "-"]).unwrap_err();
+        assert!(err.source().is_some());
+        let boxed: Box<dyn Error> = Box::new(err);
+        assert_eq!(boxed.to_string(), "invalid symbol table: the symbol sequences `+-` and `+` `-` both spell out `+-`");
+    }
+
     #[test]
     fn test_errors() {
         let src = "{]";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::InvalidClosingBracket {
             open_pos: tp(0),
             close_pos: tp(1),
         });
 
         let src = "[";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::UnclosedBracket {
             open_pos: tp(0),
         });
 
         let src = "#";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::UnexpectedChar {
             pos: tp(0),
             c: '#',
         });
 
         let src = "]";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::UnexpectedClosingBracket {
             pos: tp(0),
             c: ']',
         });
 
         let src = "\"";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::UnclosedString {
             start_pos: tp(0),
         });
 
         let src = r"'\x2g'";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::InvalidEscapeDigit {
             c: 'g',
             pos: tp(4),
         });
 
         let src = r"'\u{110000}'";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::InvalidEscapeCode {
             code: 0x110000,
             pos: tp(1),
         });
 
         let src = r"'\q'";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::InvalidEscapeChar {
             c: 'q',
             pos: tp(2),
         });
 
         let src = r"'\u{123456789}'";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::InvalidUnicodeEscape {
             pos: tp(1),
         });
 
         let src = r"'\u123'";
-        let err = lex(src, &[]).unwrap_err();
+        let err = lex(FileId(0), src, &[]).unwrap_err();
         assert_eq!(err, LexError::InvalidUnicodeEscapeSyntax {
             pos: tp(3),
         });
+
+        let src = "0b1021";
+        let err = lex(FileId(0), src, &[]).unwrap_err();
+        assert_eq!(err, LexError::InvalidDigit {
+            c: '2',
+            radix: 2,
+            pos: tp(4),
+        });
+
+        let src = "0x_";
+        let err = lex(FileId(0), src, &[]).unwrap_err();
+        assert_eq!(err, LexError::MissingDigits {
+            pos: tp(2),
+        });
+
+        let src = "1e+";
+        let err = lex(FileId(0), src, &[]).unwrap_err();
+        assert_eq!(err, LexError::MissingExponentDigits {
+            pos: tp(3),
+        });
     }
 }
 
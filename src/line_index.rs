@@ -0,0 +1,190 @@
+use crate::span::TextPos;
+use crate::lsp::{LspPosition, PositionEncoding};
+use crate::source_map::FileId;
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// The position of a line in the source.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    /// The byte offset of the start of the line.
+    start: usize,
+    /// The byte offset of the end of the line, not counting the line ending.
+    end: usize,
+    /// Whether the line is entirely printable ASCII, in which case every kind of column is the
+    /// same as the byte offset into the line.
+    ascii: bool,
+}
+
+/// An index of the lines in a source text, for converting between byte offsets and `TextPos`s
+/// without walking the text from the start. Finding the line of a position takes O(log n) time.
+/// Columns on lines containing anything but printable ASCII are then counted from the start of
+/// the line.
+///
+/// Lines end at a `\n`, a `\r\n` or a lone `\r`, the same as for `TextPos`.
+#[derive(Debug, Clone)]
+pub struct LineIndex<'s> {
+    file: FileId,
+    src: &'s str,
+    lines: Vec<Line>,
+}
+
+impl<'s> LineIndex<'s> {
+    /// Index the lines of `src`. Positions from the index will be in `file`.
+    pub fn new(file: FileId, src: &'s str) -> LineIndex<'s> {
+        let bytes = src.as_bytes();
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut ascii = true;
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\n' | b'\r' => {
+                    lines.push(Line {
+                        start,
+                        end: i,
+                        ascii,
+                    });
+                    i += match &bytes[i..] {
+                        [b'\r', b'\n', ..] => 2,
+                        _ => 1,
+                    };
+                    start = i;
+                    ascii = true;
+                    continue;
+                },
+                b' '..=b'~' => (),
+                _ => ascii = false,
+            }
+            i += 1;
+        }
+        lines.push(Line {
+            start,
+            end: bytes.len(),
+            ascii,
+        });
+        LineIndex {
+            file,
+            src,
+            lines,
+        }
+    }
+
+    /// The source text which was indexed.
+    pub fn src(&self) -> &'s str {
+        self.src
+    }
+
+    /// The number of lines. This is always at least one, and a source ending with a line ending
+    /// has an empty last line.
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The byte range of the line with the given number, not including the line ending.
+    pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        self.lines.get(line).map(|l| l.start..l.end)
+    }
+
+    /// The text of the line with the given number, not including the line ending.
+    pub fn line(&self, line: usize) -> Option<&'s str> {
+        self.line_range(line).map(|range| &self.src[range])
+    }
+
+    /// The start of the line with the given number.
+    fn line_start(&self, line: usize) -> TextPos {
+        TextPos {
+            line,
+            byte: self.lines[line].start,
+            ..TextPos::start_of(self.file)
+        }
+    }
+
+    /// Get the position of the given byte offset. Returns `None` if the offset is past the end of
+    /// the source or isn't on a character boundary.
+    pub fn pos(&self, byte: usize) -> Option<TextPos> {
+        if !self.src.is_char_boundary(byte) {
+            return None;
+        }
+        let line = match self.lines.binary_search_by_key(&byte, |l| l.start) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let info = self.lines[line];
+        if info.ascii && byte <= info.end {
+            let n = byte - info.start;
+            return Some(TextPos {
+                col: n,
+                line,
+                byte,
+                byte_col: n,
+                char_col: n,
+                utf16_col: n,
+                file: self.file,
+            });
+        }
+        let mut pos = self.line_start(line);
+        while pos.byte < byte {
+            pos = pos.next(self.src)?.1;
+        }
+        Some(pos)
+    }
+
+    /// Get the position at `column` on the given line, with the column counted in `encoding`. A
+    /// column past the end of the line gives the end of the line, and one which falls inside a
+    /// character is rounded down to the start of that character. Returns `None` if there's no
+    /// such line.
+    pub fn line_pos(&self, line: usize, column: usize, encoding: PositionEncoding) -> Option<TextPos> {
+        let info = *self.lines.get(line)?;
+        if info.ascii {
+            return self.pos(info.start + column.min(info.end - info.start));
+        }
+        Some(seek_column(self.src, self.line_start(line), column, encoding))
+    }
+
+    /// Find the position which an LSP position refers to. See `TextPos::from_lsp`.
+    pub fn from_lsp(&self, pos: LspPosition, encoding: PositionEncoding) -> Option<TextPos> {
+        self.line_pos(pos.line as usize, pos.character as usize, encoding)
+    }
+}
+
+/// Walk forward from `pos` until reaching `column`, counted in `encoding`, or the end of the line.
+pub(crate) fn seek_column(src: &str, mut pos: TextPos, column: usize, encoding: PositionEncoding) -> TextPos {
+    while let Some((c, next)) = pos.next(src) {
+        if c == '\r' || next.line != pos.line || next.column(encoding) > column {
+            break;
+        }
+        pos = next;
+    }
+    pos
+}
+
+/// Find the byte offset of the start of the given line by scanning `src`.
+pub(crate) fn find_line_start(src: &str, line: usize) -> Option<usize> {
+    let mut start = 0;
+    for _ in 0..line {
+        let rest = &src[start..];
+        let i = rest.find(['\n', '\r'])?;
+        start += match rest[i..].starts_with("\r\n") {
+            true => i + 2,
+            false => i + 1,
+        };
+    }
+    Some(start)
+}
+
+/// Find the byte range of the line containing `byte`, not including the line ending.
+pub(crate) fn line_bounds(src: &str, byte: usize) -> Range<usize> {
+    let is_break = |c| c == '\n' || c == '\r';
+    let start = match src[..byte].rfind(is_break) {
+        // `byte` is between the `\r` and `\n` of a line ending, which doesn't start a new line.
+        Some(i) if i + 1 == byte && src[i..].starts_with("\r\n") => {
+            src[..i].rfind(is_break).map_or(0, |j| j + 1)
+        },
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let end = src[start..].find(is_break).map_or(src.len(), |i| start + i);
+    start..end
+}
@@ -0,0 +1,98 @@
+use crate::span::{TextPos, Span};
+use crate::line_index::{find_line_start, seek_column};
+use crate::source_map::FileId;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// How the character offsets of an `LspPosition` are counted. Language servers and clients
+/// agree on one of these when they initialize, UTF-16 being the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PositionEncoding {
+    /// Offsets count bytes of UTF-8.
+    Utf8,
+    /// Offsets count UTF-16 code units.
+    #[default]
+    Utf16,
+    /// Offsets count characters.
+    Utf32,
+}
+
+/// A position as used by the Language Server Protocol: a line and an offset into that line,
+/// both counting from zero. The offset is counted according to a `PositionEncoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LspPosition {
+    pub line: u32,
+    pub character: u32,
+}
+
+/// A range as used by the Language Server Protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LspRange {
+    /// The start position of the range (inclusive).
+    pub start: LspPosition,
+    /// The end position of the range (exclusive).
+    pub end: LspPosition,
+}
+
+impl TextPos {
+    /// The column of this position counted in `encoding`.
+    pub fn column(&self, encoding: PositionEncoding) -> usize {
+        match encoding {
+            PositionEncoding::Utf8 => self.byte_col,
+            PositionEncoding::Utf16 => self.utf16_col,
+            PositionEncoding::Utf32 => self.char_col,
+        }
+    }
+
+    /// Convert to an LSP position with the offset counted in `encoding`.
+    pub fn to_lsp(&self, encoding: PositionEncoding) -> LspPosition {
+        LspPosition {
+            line: self.line as u32,
+            character: self.column(encoding) as u32,
+        }
+    }
+
+    /// Find the position in `src`, the source of `file`, which an LSP position refers to. As the
+    /// protocol requires, an offset past the end of the line refers to the end of the line. An
+    /// offset which falls inside a character, such as between the two halves of a UTF-16
+    /// surrogate pair, is rounded down to the start of that character. Returns `None` if `src`
+    /// doesn't have that many lines.
+    ///
+    /// This scans `src` from the start, so use a `LineIndex` to convert many positions.
+    pub fn from_lsp(file: FileId, src: &str, pos: LspPosition, encoding: PositionEncoding)
+        -> Option<TextPos>
+    {
+        let line = pos.line as usize;
+        let start = TextPos {
+            line,
+            byte: find_line_start(src, line)?,
+            ..TextPos::start_of(file)
+        };
+        Some(seek_column(src, start, pos.character as usize, encoding))
+    }
+}
+
+impl Span {
+    /// Convert to an LSP range with offsets counted in `encoding`.
+    pub fn to_lsp(&self, encoding: PositionEncoding) -> LspRange {
+        LspRange {
+            start: self.start.to_lsp(encoding),
+            end: self.end.to_lsp(encoding),
+        }
+    }
+
+    /// Find the span of `src`, the source of `file`, which an LSP range refers to. See
+    /// `TextPos::from_lsp`.
+    pub fn from_lsp(file: FileId, src: &str, range: LspRange, encoding: PositionEncoding)
+        -> Option<Span>
+    {
+        Some(Span {
+            start: TextPos::from_lsp(file, src, range.start, encoding)?,
+            end: TextPos::from_lsp(file, src, range.end, encoding)?,
+        })
+    }
+}
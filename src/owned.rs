@@ -0,0 +1,448 @@
+use crate::span::TextPos;
+use crate::token::{Token, TokenKind, StringLit, NumberLit};
+use crate::tokens::TokensBuf;
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::{self, Vec};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+use core::slice;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+/// A slice of a reference-counted string. Cloning one is cheap and it can be sent between
+/// threads, so the text of an `OwnedTokens` tree stays alive for as long as the tree does
+/// without copying the source for every token.
+#[derive(Clone)]
+pub struct ArcStr {
+    src: Arc<str>,
+    start: usize,
+    end: usize,
+}
+
+impl ArcStr {
+    /// Create an `ArcStr` covering all of `src`.
+    pub fn new(src: Arc<str>) -> ArcStr {
+        let end = src.len();
+        ArcStr {
+            src,
+            start: 0,
+            end,
+        }
+    }
+
+    /// Create an `ArcStr` covering `s`, which should be a slice of `src`. If it isn't, `s` is
+    /// copied into a new allocation instead.
+    fn slice(src: &Arc<str>, s: &str) -> ArcStr {
+        let base = src.as_ptr() as usize;
+        let ptr = s.as_ptr() as usize;
+        if ptr < base || ptr + s.len() > base + src.len() {
+            return ArcStr::from(s);
+        }
+        ArcStr {
+            src: src.clone(),
+            start: ptr - base,
+            end: ptr - base + s.len(),
+        }
+    }
+
+    /// Get the text as a `&str`.
+    pub fn as_str(&self) -> &str {
+        &self.src[self.start..self.end]
+    }
+
+    /// The whole string that this is a slice of.
+    pub fn source(&self) -> &Arc<str> {
+        &self.src
+    }
+}
+
+impl<'a> From<&'a str> for ArcStr {
+    fn from(s: &'a str) -> ArcStr {
+        ArcStr::new(Arc::from(s))
+    }
+}
+
+impl From<String> for ArcStr {
+    fn from(s: String) -> ArcStr {
+        ArcStr::new(Arc::from(s))
+    }
+}
+
+impl Deref for ArcStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for ArcStr {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for ArcStr {
+    fn eq(&self, other: &ArcStr) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for ArcStr {}
+
+impl<'a> PartialEq<&'a str> for ArcStr {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Hash for ArcStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl fmt::Debug for ArcStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for ArcStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Serialized as a plain string.
+#[cfg(feature = "serde")]
+impl Serialize for ArcStr {
+    fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ArcStr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ArcStr, D::Error> {
+        String::deserialize(deserializer).map(ArcStr::from)
+    }
+}
+
+/// An owned version of `TokenKind`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OwnedTokenKind<S = usize> {
+    Ident(ArcStr),
+    Whitespace(ArcStr),
+    Symbol(ArcStr, S),
+    Bracket(char, OwnedTokens<S>, Option<char>),
+    String(OwnedStringLit),
+    Number(OwnedNumberLit),
+    Comment(ArcStr),
+    Error(ArcStr),
+}
+
+/// An owned version of `StringLit`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OwnedStringLit {
+    pub quote: char,
+    pub hashes: Option<usize>,
+    pub raw: ArcStr,
+    pub value: ArcStr,
+}
+
+/// An owned version of `NumberLit`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OwnedNumberLit {
+    pub raw: ArcStr,
+    pub radix: u32,
+    pub integer: ArcStr,
+    pub fraction: Option<ArcStr>,
+    pub exponent: Option<ArcStr>,
+    pub suffix: Option<ArcStr>,
+}
+
+/// An owned version of `Token`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OwnedToken<S = usize> {
+    pub start: TextPos,
+    pub end: TextPos,
+    pub kind: OwnedTokenKind<S>,
+}
+
+/// A token tree which doesn't borrow from the source text, so it can be stored in long-lived
+/// structures or sent to other threads after the source is gone. Text is held as `ArcStr`
+/// slices sharing a single copy of the source.
+///
+/// Build one with `TokensBuf::into_owned` and get a borrowed `TokensBuf` back with
+/// `OwnedTokens::to_tokens_buf`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OwnedTokens<S = usize> {
+    pub tokens: Vec<OwnedToken<S>>,
+    pub start: TextPos,
+    pub end: TextPos,
+}
+
+impl<'s, S> TokensBuf<'s, S> {
+    /// Convert to an `OwnedTokens` tree. `src` should hold the source these tokens were lexed
+    /// from, in which case the tree shares it rather than copying any text.
+    pub fn into_owned(self, src: &Arc<str>) -> OwnedTokens<S> {
+        // Uses an explicit stack of brackets, as `write_tokens` does.
+        struct Level<'s, S> {
+            iter: vec::IntoIter<Token<'s, S>>,
+            done: OwnedTokens<S>,
+            /// The open and close characters and span of the bracket token, or `None` at the
+            /// top level.
+            bracket: Option<(char, Option<char>, TextPos, TextPos)>,
+        }
+
+        let mut stack = vec![Level {
+            iter: self.tokens.into_iter(),
+            done: OwnedTokens {
+                tokens: Vec::new(),
+                start: self.start,
+                end: self.end,
+            },
+            bracket: None,
+        }];
+        loop {
+            let level = stack.last_mut().unwrap();
+            match level.iter.next() {
+                Some(Token { kind: TokenKind::Bracket(open, inner, close), start, end }) => {
+                    stack.push(Level {
+                        iter: inner.tokens.into_iter(),
+                        done: OwnedTokens {
+                            tokens: Vec::new(),
+                            start: inner.start,
+                            end: inner.end,
+                        },
+                        bracket: Some((open, close, start, end)),
+                    });
+                },
+                Some(token) => level.done.tokens.push(token.into_owned(src)),
+                None => {
+                    let level = stack.pop().unwrap();
+                    let (open, close, start, end) = match level.bracket {
+                        Some(bracket) => bracket,
+                        None => return level.done,
+                    };
+                    stack.last_mut().unwrap().done.tokens.push(OwnedToken {
+                        kind: OwnedTokenKind::Bracket(open, level.done, close),
+                        start,
+                        end,
+                    });
+                },
+            }
+        }
+    }
+}
+
+impl<'s, S> Token<'s, S> {
+    /// Convert to an `OwnedToken`. See `TokensBuf::into_owned`.
+    pub fn into_owned(self, src: &Arc<str>) -> OwnedToken<S> {
+        let slice = |s| ArcStr::slice(src, s);
+        let kind = match self.kind {
+            TokenKind::Ident(s) => OwnedTokenKind::Ident(slice(s)),
+            TokenKind::Whitespace(s) => OwnedTokenKind::Whitespace(slice(s)),
+            TokenKind::Symbol(s, value) => OwnedTokenKind::Symbol(slice(s), value),
+            TokenKind::Bracket(open, tokens, close) => {
+                OwnedTokenKind::Bracket(open, tokens.into_owned(src), close)
+            },
+            TokenKind::String(string) => OwnedTokenKind::String(OwnedStringLit {
+                quote: string.quote,
+                hashes: string.hashes,
+                raw: slice(string.raw),
+                value: match string.value {
+                    Cow::Borrowed(s) => slice(s),
+                    Cow::Owned(s) => ArcStr::from(s),
+                },
+            }),
+            TokenKind::Number(number) => OwnedTokenKind::Number(OwnedNumberLit {
+                raw: slice(number.raw),
+                radix: number.radix,
+                integer: slice(number.integer),
+                fraction: number.fraction.map(&slice),
+                exponent: number.exponent.map(&slice),
+                suffix: number.suffix.map(&slice),
+            }),
+            TokenKind::Comment(s) => OwnedTokenKind::Comment(slice(s)),
+            TokenKind::Error(s) => OwnedTokenKind::Error(slice(s)),
+        };
+        OwnedToken {
+            kind,
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl<S: Clone> OwnedTokens<S> {
+    /// Get a borrowed `TokensBuf` of the same tokens. No text is copied.
+    pub fn to_tokens_buf(&self) -> TokensBuf<'_, S> {
+        // Uses an explicit stack of brackets, as `write_tokens` does.
+        struct Level<'o, S> {
+            iter: slice::Iter<'o, OwnedToken<S>>,
+            done: TokensBuf<'o, S>,
+            /// The open and close characters and span of the bracket token, or `None` at the
+            /// top level.
+            bracket: Option<(char, Option<char>, TextPos, TextPos)>,
+        }
+
+        let mut stack = vec![Level {
+            iter: self.tokens.iter(),
+            done: TokensBuf {
+                tokens: Vec::new(),
+                start: self.start,
+                end: self.end,
+            },
+            bracket: None,
+        }];
+        loop {
+            let level = stack.last_mut().unwrap();
+            match level.iter.next() {
+                Some(&OwnedToken { kind: OwnedTokenKind::Bracket(open, ref inner, close), start, end }) => {
+                    stack.push(Level {
+                        iter: inner.tokens.iter(),
+                        done: TokensBuf {
+                            tokens: Vec::new(),
+                            start: inner.start,
+                            end: inner.end,
+                        },
+                        bracket: Some((open, close, start, end)),
+                    });
+                },
+                Some(token) => level.done.tokens.push(token.to_token()),
+                None => {
+                    let level = stack.pop().unwrap();
+                    let (open, close, start, end) = match level.bracket {
+                        Some(bracket) => bracket,
+                        None => return level.done,
+                    };
+                    stack.last_mut().unwrap().done.tokens.push(Token {
+                        kind: TokenKind::Bracket(open, level.done, close),
+                        start,
+                        end,
+                    });
+                },
+            }
+        }
+    }
+}
+
+impl<S: Clone> OwnedToken<S> {
+    /// Get a borrowed `Token` of the same token. No text is copied.
+    pub fn to_token(&self) -> Token<'_, S> {
+        let kind = match self.kind {
+            OwnedTokenKind::Ident(ref s) => TokenKind::Ident(s),
+            OwnedTokenKind::Whitespace(ref s) => TokenKind::Whitespace(s),
+            OwnedTokenKind::Symbol(ref s, ref value) => TokenKind::Symbol(s, value.clone()),
+            OwnedTokenKind::Bracket(open, ref tokens, close) => {
+                TokenKind::Bracket(open, tokens.to_tokens_buf(), close)
+            },
+            OwnedTokenKind::String(ref string) => TokenKind::String(StringLit {
+                quote: string.quote,
+                hashes: string.hashes,
+                raw: &string.raw,
+                value: Cow::Borrowed(&string.value),
+            }),
+            OwnedTokenKind::Number(ref number) => TokenKind::Number(NumberLit {
+                raw: &number.raw,
+                radix: number.radix,
+                integer: &number.integer,
+                fraction: number.fraction.as_ref().map(|s| s.as_str()),
+                exponent: number.exponent.as_ref().map(|s| s.as_str()),
+                suffix: number.suffix.as_ref().map(|s| s.as_str()),
+            }),
+            OwnedTokenKind::Comment(ref s) => TokenKind::Comment(s),
+            OwnedTokenKind::Error(ref s) => TokenKind::Error(s),
+        };
+        Token {
+            kind,
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl<S> fmt::Display for OwnedTokens<S> {
+    /// Prints the tokens as they appeared in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_owned_tokens(f, &self.tokens[..])
+    }
+}
+
+impl<S> fmt::Display for OwnedToken<S> {
+    /// Prints the token as it appeared in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            OwnedTokenKind::Bracket(open, ref tokens, close) => {
+                write!(f, "{}", open)?;
+                write_owned_tokens(f, &tokens.tokens[..])?;
+                match close {
+                    Some(close) => write!(f, "{}", close),
+                    None => Ok(()),
+                }
+            },
+            ref kind => write_leaf(f, kind),
+        }
+    }
+}
+
+/// Print a token which isn't a bracket.
+fn write_leaf<S>(f: &mut fmt::Formatter, kind: &OwnedTokenKind<S>) -> fmt::Result {
+    match *kind {
+        OwnedTokenKind::Ident(ref s) |
+        OwnedTokenKind::Whitespace(ref s) |
+        OwnedTokenKind::Symbol(ref s, _) |
+        OwnedTokenKind::Comment(ref s) |
+        OwnedTokenKind::Error(ref s) => f.write_str(s),
+        OwnedTokenKind::String(ref string) => {
+            let string = StringLit {
+                quote: string.quote,
+                hashes: string.hashes,
+                raw: &string.raw,
+                value: Cow::Borrowed(&string.value),
+            };
+            write!(f, "{}", string)
+        },
+        OwnedTokenKind::Number(ref number) => f.write_str(&number.raw),
+        OwnedTokenKind::Bracket(..) => unreachable!("brackets are printed by write_owned_tokens"),
+    }
+}
+
+/// Print a sequence of owned tokens. See `write_tokens`.
+fn write_owned_tokens<S>(f: &mut fmt::Formatter, tokens: &[OwnedToken<S>]) -> fmt::Result {
+    let mut stack: Vec<(slice::Iter<OwnedToken<S>>, Option<char>)> = vec![(tokens.iter(), None)];
+    loop {
+        let next = match stack.last_mut() {
+            Some(&mut (ref mut iter, _)) => iter.next(),
+            None => return Ok(()),
+        };
+        match next {
+            Some(token) => {
+                match token.kind {
+                    OwnedTokenKind::Bracket(open, ref inner, close) => {
+                        write!(f, "{}", open)?;
+                        stack.push((inner.tokens.iter(), close));
+                    },
+                    ref kind => write_leaf(f, kind)?,
+                }
+            },
+            None => {
+                if let Some((_, Some(close))) = stack.pop() {
+                    write!(f, "{}", close)?;
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::span::TextPos;
+use crate::token::{Token, TokenKind, StringLit, NumberLit};
+use crate::tokens::TokensBuf;
+use crate::config::{CommentSyntax, LexerConfig};
+use crate::lex::{LexError, build_tokens, lex_with_config};
+use crate::stream::Lexer;
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// A change to a source text: the bytes in `range` were replaced with `text`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Edit<'e> {
+    /// The range of bytes in the old source which were replaced.
+    pub range: Range<usize>,
+    /// The text they were replaced with.
+    pub text: &'e str,
+}
+
+/// Maps the positions and slices of tokens which weren't affected by an edit from the old source
+/// into the new one. Everything before the edit stays where it was, everything after the closing
+/// bracket of the re-lexed subtree moves by the same amount as that bracket.
+///
+/// Slices are mapped by their offset into `old_src`. If the old tree wasn't lexed from that
+/// exact string, eg. because the caller passed a copy of it, a slice won't lie inside it and
+/// the mapping gives `None`.
+struct Rebase<'o, 's> {
+    old_src: &'o str,
+    src: &'s str,
+    old_close: TextPos,
+    new_close: TextPos,
+}
+
+impl<'o, 's> Rebase<'o, 's> {
+    fn pos(&self, pos: TextPos) -> TextPos {
+        if pos.byte < self.old_close.byte {
+            return pos;
+        }
+        let line = pos.line - self.old_close.line + self.new_close.line;
+        let byte = pos.byte - self.old_close.byte + self.new_close.byte;
+        if pos.line != self.old_close.line {
+            return TextPos {
+                line,
+                byte,
+                ..pos
+            };
+        }
+        let (old, new) = (self.old_close, self.new_close);
+        TextPos {
+            col: pos.col - old.col + new.col,
+            line,
+            byte,
+            byte_col: pos.byte_col - old.byte_col + new.byte_col,
+            char_col: pos.char_col - old.char_col + new.char_col,
+            utf16_col: pos.utf16_col - old.utf16_col + new.utf16_col,
+            file: pos.file,
+        }
+    }
+
+    fn str(&self, s: &'o str) -> Option<&'s str> {
+        let mut start = (s.as_ptr() as usize).checked_sub(self.old_src.as_ptr() as usize)?;
+        if start + s.len() > self.old_src.len() {
+            return None;
+        }
+        if start >= self.old_close.byte {
+            start = start - self.old_close.byte + self.new_close.byte;
+        }
+        self.src.get(start..start + s.len())
+    }
+
+    fn opt_str(&self, s: Option<&'o str>) -> Option<Option<&'s str>> {
+        match s {
+            Some(s) => self.str(s).map(Some),
+            None => Some(None),
+        }
+    }
+
+    fn tokens<S: Clone>(&self, tokens: &TokensBuf<'o, S>) -> Option<TokensBuf<'s, S>> {
+        Some(TokensBuf {
+            tokens: tokens.tokens.iter().map(|token| self.token(token)).collect::<Option<_>>()?,
+            start: self.pos(tokens.start),
+            end: self.pos(tokens.end),
+        })
+    }
+
+    fn token<S: Clone>(&self, token: &Token<'o, S>) -> Option<Token<'s, S>> {
+        let kind = match token.kind {
+            TokenKind::Ident(s) => TokenKind::Ident(self.str(s)?),
+            TokenKind::Whitespace(s) => TokenKind::Whitespace(self.str(s)?),
+            TokenKind::Symbol(s, ref value) => TokenKind::Symbol(self.str(s)?, value.clone()),
+            TokenKind::Bracket(open, ref tokens, close) => {
+                TokenKind::Bracket(open, self.tokens(tokens)?, close)
+            },
+            TokenKind::String(ref string) => TokenKind::String(StringLit {
+                quote: string.quote,
+                hashes: string.hashes,
+                raw: self.str(string.raw)?,
+                value: match string.value {
+                    Cow::Borrowed(s) => Cow::Borrowed(self.str(s)?),
+                    Cow::Owned(ref s) => Cow::Owned(s.clone()),
+                },
+            }),
+            TokenKind::Number(ref number) => TokenKind::Number(NumberLit {
+                raw: self.str(number.raw)?,
+                radix: number.radix,
+                integer: self.str(number.integer)?,
+                fraction: self.opt_str(number.fraction)?,
+                exponent: self.opt_str(number.exponent)?,
+                suffix: self.opt_str(number.suffix)?,
+            }),
+            TokenKind::Comment(s) => TokenKind::Comment(self.str(s)?),
+            TokenKind::Error(s) => TokenKind::Error(self.str(s)?),
+        };
+        Some(Token {
+            kind,
+            start: self.pos(token.start),
+            end: self.pos(token.end),
+        })
+    }
+
+    /// Copy `tokens`, replacing the token found by following `path` down through the brackets
+    /// with `replacement`.
+    fn splice<S: Clone>(&self,
+                        tokens: &TokensBuf<'o, S>,
+                        path: &[usize],
+                        replacement: &mut Option<Token<'s, S>>) -> Option<TokensBuf<'s, S>>
+    {
+        let mut ret = TokensBuf {
+            tokens: Vec::with_capacity(tokens.tokens.len()),
+            start: self.pos(tokens.start),
+            end: self.pos(tokens.end),
+        };
+        for (i, token) in tokens.tokens.iter().enumerate() {
+            if i != path[0] {
+                ret.tokens.push(self.token(token)?);
+                continue;
+            }
+            if path.len() == 1 {
+                ret.tokens.push(replacement.take().unwrap());
+                continue;
+            }
+            let kind = match token.kind {
+                TokenKind::Bracket(open, ref inner, close) => {
+                    TokenKind::Bracket(open, self.splice(inner, &path[1..], replacement)?, close)
+                },
+                _ => unreachable!(),
+            };
+            ret.tokens.push(Token {
+                kind,
+                start: self.pos(token.start),
+                end: self.pos(token.end),
+            });
+        }
+        Some(ret)
+    }
+}
+
+/// Find the path through the tree to the innermost bracket whose contents contain the edited
+/// range, without touching the brackets themselves.
+fn find_path<S>(tokens: &TokensBuf<S>, range: &Range<usize>) -> Vec<usize> {
+    let mut path = Vec::new();
+    let mut tokens = tokens;
+    'outer: loop {
+        for (i, token) in tokens.tokens.iter().enumerate() {
+            if let TokenKind::Bracket(_, ref inner, _) = token.kind {
+                if inner.start.byte <= range.start && range.end <= inner.end.byte {
+                    path.push(i);
+                    tokens = inner;
+                    continue 'outer;
+                }
+            }
+        }
+        return path;
+    }
+}
+
+/// The number of bytes after the opening bracket `open` which can be part of a comment
+/// delimiter starting with that bracket.
+fn comment_reach(comments: &CommentSyntax, open: char) -> usize {
+    comments.line.iter().copied()
+        .chain(comments.block.iter().map(|&(delim, _)| delim))
+        .filter(|delim| delim.starts_with(open))
+        .map(|delim| delim.len() - open.len_utf8())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Get the bracket token at the end of `path`, along with the characters and positions of the
+/// brackets enclosing its contents.
+fn follow_path<'t, 'o, S>(tokens: &'t TokensBuf<'o, S>, path: &[usize])
+    -> (&'t Token<'o, S>, Vec<(char, TextPos)>)
+{
+    let mut stack = Vec::new();
+    let mut tokens = tokens;
+    let mut token = None;
+    for &i in path {
+        let t = &tokens.tokens[i];
+        if let TokenKind::Bracket(c, ref inner, _) = t.kind {
+            stack.push((c, t.start));
+            tokens = inner;
+        }
+        token = Some(t);
+    }
+    (token.unwrap(), stack)
+}
+
+pub(crate) fn relex_with_config<'o, 's, S: Clone>(config: &LexerConfig<'s, S>,
+                                                  old: &TokensBuf<'o, S>,
+                                                  old_src: &'o str,
+                                                  edit: &Edit,
+                                                  src: &'s str) -> Result<TokensBuf<'s, S>, LexError>
+{
+    let file = old.start.file;
+    let range = &edit.range;
+    if range.start > range.end || range.end > old_src.len() ||
+       src.len() != old_src.len() - (range.end - range.start) + edit.text.len()
+    {
+        return lex_with_config(file, src, config);
+    }
+
+    // Try re-lexing the contents of the innermost bracket containing the edit. If the edit
+    // changes where that bracket gets closed, or introduces an error, try again with the bracket
+    // containing it and so on, until we end up re-lexing the whole source.
+    let path = find_path(old, range);
+    for depth in (1..path.len() + 1).rev() {
+        let (bracket, stack) = follow_path(old, &path[..depth]);
+        let (c, inner) = match bracket.kind {
+            TokenKind::Bracket(c, ref inner, _) => (c, inner),
+            _ => unreachable!(),
+        };
+        // Comments are lexed before brackets, so an edit just after the opening bracket could
+        // turn it into the start of a comment.
+        if range.start < inner.start.byte + comment_reach(config.comment_syntax(), c) {
+            continue;
+        }
+        let expected_close = inner.end.byte - range.end + range.start + edit.text.len();
+        let mut lexer = Lexer::resume(src, config, inner.start, stack);
+        let (tokens, close_char, close) = match build_tokens(&mut lexer, Err) {
+            Ok((tokens, Some((close_char, close)))) => (tokens, close_char, close),
+            _ => continue,
+        };
+        if close.start.byte != expected_close {
+            continue;
+        }
+
+        let rebase = Rebase {
+            old_src,
+            src,
+            old_close: inner.end,
+            new_close: close.start,
+        };
+        let replacement = Token {
+            kind: TokenKind::Bracket(c, TokensBuf {
+                tokens,
+                start: inner.start,
+                end: close.start,
+            }, close_char),
+            start: bracket.start,
+            end: close.end,
+        };
+        // If `old` wasn't lexed from `old_src` its text can't be mapped into `src`, so give up
+        // and lex from scratch.
+        return match rebase.splice(old, &path[..depth], &mut Some(replacement)) {
+            Some(tokens) => Ok(tokens),
+            None => lex_with_config(file, src, config),
+        };
+    }
+    lex_with_config(file, src, config)
+}
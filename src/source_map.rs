@@ -0,0 +1,92 @@
+use crate::lex::LexError;
+use crate::line_index::LineIndex;
+use crate::diagnostic::Diagnostic;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// Identifies a source file. Every `TextPos` records the file it is in, so positions from
+/// different files can be told apart. `SourceMap::add_file` hands out ids counting up from zero,
+/// and the default id of zero can be used when there is only one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileId(pub u32);
+
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// A file registered with a `SourceMap`.
+#[derive(Debug, Clone)]
+struct SourceFile<'s> {
+    name: String,
+    lines: LineIndex<'s>,
+}
+
+/// A set of source files, each with a name and a `FileId`. Lex each file with the id it was
+/// given and the map can find the file and line of any position or error, which is needed to
+/// report errors when several files are lexed together.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap<'s> {
+    files: Vec<SourceFile<'s>>,
+}
+
+impl<'s> SourceMap<'s> {
+    /// Create an empty source map.
+    pub fn new() -> SourceMap<'s> {
+        SourceMap {
+            files: Vec::new(),
+        }
+    }
+
+    /// Register a file with the given name and source text, returning the id to lex it with.
+    pub fn add_file(&mut self, name: &str, src: &'s str) -> FileId {
+        let file = FileId(self.files.len() as u32);
+        self.files.push(SourceFile {
+            name: String::from(name),
+            lines: LineIndex::new(file, src),
+        });
+        file
+    }
+
+    /// The ids of all the registered files, in the order they were added.
+    pub fn files(&self) -> impl Iterator<Item = FileId> {
+        (0..self.files.len() as u32).map(FileId)
+    }
+
+    fn file(&self, file: FileId) -> &SourceFile<'s> {
+        match self.files.get(file.0 as usize) {
+            Some(f) => f,
+            None => panic!("file {} is not in this source map", file),
+        }
+    }
+
+    /// The name of a file. Panics if `file` wasn't registered with this map.
+    pub fn name(&self, file: FileId) -> &str {
+        &self.file(file).name
+    }
+
+    /// The source text of a file. Panics if `file` wasn't registered with this map.
+    pub fn src(&self, file: FileId) -> &'s str {
+        self.file(file).lines.src()
+    }
+
+    /// The line index of a file, for converting byte offsets into positions. Panics if `file`
+    /// wasn't registered with this map.
+    pub fn line_index(&self, file: FileId) -> &LineIndex<'s> {
+        &self.file(file).lines
+    }
+
+    /// Render a `LexError` from lexing one of the files as a human-readable report. See
+    /// `LexError::render`.
+    pub fn render(&self, error: &LexError) -> String {
+        let src = error.pos().map_or("", |pos| self.src(pos.file));
+        Diagnostic::from_lex_error(error, src).render_in(self)
+    }
+}
@@ -1,19 +1,37 @@
+use crate::source_map::FileId;
+
 use unicode_width::UnicodeWidthChar;
 
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
 /// A position in a text document.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TextPos {
     /// The column number from the start of the line. Some wierd characters may take up multiple
     /// columns or no columns.
     pub col: usize,
-    /// The line number
+    /// The line number. Lines end at a `\n`, a `\r\n` or a lone `\r`.
     pub line: usize,
     /// The byte position from the start of the document.
     pub byte: usize,
+    /// The number of bytes from the start of the line.
+    pub byte_col: usize,
+    /// The number of characters from the start of the line.
+    pub char_col: usize,
+    /// The number of UTF-16 code units from the start of the line. This is what the Language
+    /// Server Protocol calls the character offset by default.
+    pub utf16_col: usize,
+    /// The file the position is in.
+    pub file: FileId,
 }
 
 /// A span of text in a text document.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Span {
     /// The start position of the span (inclusive).
     pub start: TextPos,
@@ -24,10 +42,19 @@ pub struct Span {
 impl TextPos {
     /// Create a TextPos at the start of a document.
     pub fn start() -> TextPos {
+        TextPos::start_of(FileId::default())
+    }
+
+    /// Create a TextPos at the start of the given file.
+    pub fn start_of(file: FileId) -> TextPos {
         TextPos {
             col: 0,
             line: 0,
             byte: 0,
+            byte_col: 0,
+            char_col: 0,
+            utf16_col: 0,
+            file,
         }
     }
 
@@ -45,11 +72,15 @@ impl TextPos {
             None => return None,
         };
 
-        let pos = if c == '\n' {
+        let pos = if c == '\n' || (c == '\r' && !src[i..].starts_with('\n')) {
             TextPos {
                 col: 0,
                 line: self.line + 1,
                 byte: i,
+                byte_col: 0,
+                char_col: 0,
+                utf16_col: 0,
+                file: self.file,
             }
         }
         else {
@@ -57,9 +88,32 @@ impl TextPos {
                 col: self.col + c.width().unwrap_or(0),
                 line: self.line,
                 byte: i,
+                byte_col: self.byte_col + c.len_utf8(),
+                char_col: self.char_col + 1,
+                utf16_col: self.utf16_col + c.len_utf16(),
+                file: self.file,
             }
         };
         Some((c, pos))
     }
 }
 
+impl Span {
+    /// The file the span is in.
+    pub fn file(&self) -> FileId {
+        self.start.file
+    }
+}
+
+impl fmt::Display for TextPos {
+    /// Formats the position as `line:col`, counting both from 1.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.col + 1)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
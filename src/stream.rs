@@ -0,0 +1,200 @@
+use crate::span::{TextPos, Span};
+use crate::token::{Token, TokenKind};
+use crate::config::LexerConfig;
+use crate::lex::{LexError, Errors, Item, lex_item};
+use crate::source_map::FileId;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// An event produced by a `Lexer`. Rather than being collected into a tree, the contents of a
+/// bracket appear between its `Open` and `Close` events.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FlatToken<'s, S = usize> {
+    /// A token other than a bracket. The kind is never `TokenKind::Bracket`.
+    Token(#[cfg_attr(feature = "serde", serde(borrow))] Token<'s, S>),
+    /// An opening bracket and its span.
+    Open(char, Span),
+    /// A closing bracket and its span. When recovering from errors, brackets left unclosed at the
+    /// end of the input are closed with `None` and an empty span.
+    Close(Option<char>, Span),
+}
+
+/// A streaming lexer. Produces the tokens of the source one at a time as `FlatToken`s, so that
+/// a parser can start work before the whole source has been lexed. Memory use is bounded by the
+/// bracket nesting depth rather than the length of the source.
+///
+/// A `Lexer` created with `Lexer::new` stops after yielding the first error. One created with
+/// `Lexer::recovering` yields each error as it is found and carries on, with the same recovery
+/// behaviour as `LexerConfig::lex_recovering`. Either way, every `Open` event is eventually
+/// matched by a `Close` event unless lexing stops at an error.
+pub struct Lexer<'c, 's: 'c, S: 'c = usize> {
+    src: &'s str,
+    config: &'c LexerConfig<'s, S>,
+    pos: TextPos,
+    /// The brackets which are currently open, along with their positions.
+    stack: Vec<(char, TextPos)>,
+    errors: Errors,
+    queue: VecDeque<Result<FlatToken<'s, S>, LexError>>,
+    done: bool,
+}
+
+impl<'c, 's: 'c, S: 'c> Lexer<'c, 's, S> {
+    /// Create a lexer which lexes `src`, the source of `file`, using `config` and stops at the
+    /// first error.
+    pub fn new(file: FileId, src: &'s str, config: &'c LexerConfig<'s, S>) -> Lexer<'c, 's, S> {
+        Lexer::with_recovery(file, src, config, false)
+    }
+
+    /// Create a lexer which lexes `src`, the source of `file`, using `config` and carries on past
+    /// errors.
+    pub fn recovering(file: FileId, src: &'s str, config: &'c LexerConfig<'s, S>) -> Lexer<'c, 's, S> {
+        Lexer::with_recovery(file, src, config, true)
+    }
+
+    fn with_recovery(file: FileId, src: &'s str, config: &'c LexerConfig<'s, S>, recover: bool)
+        -> Lexer<'c, 's, S>
+    {
+        let mut lexer = Lexer::resume(src, config, TextPos::start_of(file), Vec::new());
+        lexer.errors.recover = recover;
+        lexer
+    }
+
+    /// Create a lexer which starts lexing at `pos` inside the brackets in `stack`, given as their
+    /// characters and positions from outermost to innermost. Stops at the first error.
+    pub(crate) fn resume(src: &'s str,
+                         config: &'c LexerConfig<'s, S>,
+                         pos: TextPos,
+                         stack: Vec<(char, TextPos)>) -> Lexer<'c, 's, S>
+    {
+        Lexer {
+            src,
+            config,
+            pos,
+            stack,
+            errors: Errors {
+                recover: false,
+                errors: Vec::new(),
+            },
+            queue: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// The position lexing has reached. Once the lexer is exhausted this is the end of the
+    /// source, unless lexing stopped at an error.
+    pub fn pos(&self) -> TextPos {
+        self.pos
+    }
+
+    /// The number of brackets which are currently open.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Lex the next item in the source, queueing the resulting event.
+    fn advance(&mut self) -> Result<(), LexError>
+        where S: Clone
+    {
+        let pos = self.pos;
+        let src = self.src;
+        let event = match lex_item(pos, src, self.config, &mut self.errors)? {
+            Item::Token(token) => {
+                self.pos = token.end;
+                FlatToken::Token(token)
+            },
+            Item::Open(c, p) => {
+                self.pos = p;
+                let max_depth = self.config.get_max_depth();
+                if max_depth.is_some_and(|max_depth| self.stack.len() >= max_depth) {
+                    self.errors.report(LexError::NestingTooDeep {
+                        pos,
+                    })?;
+                    FlatToken::Token(Token {
+                        kind: TokenKind::Error(&src[pos.byte..p.byte]),
+                        start: pos,
+                        end: p,
+                    })
+                }
+                else {
+                    self.stack.push((c, pos));
+                    FlatToken::Open(c, Span {
+                        start: pos,
+                        end: p,
+                    })
+                }
+            },
+            Item::Close(c, p) => {
+                self.pos = p;
+                match self.stack.pop() {
+                    Some((open, open_pos)) => {
+                        if c != self.config.close_bracket(open) {
+                            self.errors.report(LexError::InvalidClosingBracket {
+                                open_pos,
+                                close_pos: pos,
+                            })?;
+                        }
+                        FlatToken::Close(Some(c), Span {
+                            start: pos,
+                            end: p,
+                        })
+                    },
+                    None => {
+                        self.errors.report(LexError::UnexpectedClosingBracket {
+                            pos,
+                            c,
+                        })?;
+                        FlatToken::Token(Token {
+                            kind: TokenKind::Error(&src[pos.byte..p.byte]),
+                            start: pos,
+                            end: p,
+                        })
+                    },
+                }
+            },
+            Item::End => {
+                match self.stack.pop() {
+                    Some((_, open_pos)) => {
+                        self.errors.report(LexError::UnclosedBracket {
+                            open_pos,
+                        })?;
+                        FlatToken::Close(None, Span {
+                            start: pos,
+                            end: pos,
+                        })
+                    },
+                    None => {
+                        self.done = true;
+                        return Ok(());
+                    },
+                }
+            },
+        };
+        self.queue.extend(self.errors.errors.drain(..).map(Err));
+        self.queue.push_back(Ok(event));
+        Ok(())
+    }
+}
+
+impl<'c, 's: 'c, S: Clone + 'c> Iterator for Lexer<'c, 's, S> {
+    type Item = Result<FlatToken<'s, S>, LexError>;
+
+    fn next(&mut self) -> Option<Result<FlatToken<'s, S>, LexError>> {
+        loop {
+            if let Some(item) = self.queue.pop_front() {
+                return Some(item);
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.advance() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
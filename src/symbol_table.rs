@@ -1,60 +1,690 @@
-use std::str::pattern::Pattern;
+use alloc::collections::{BinaryHeap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
 
-/// Error returned by `validate_symbol_table`. Represents two sequences of symbols that cannot be
-/// parsed unambiguously because they would appear as the same string of text.
+use unicode_brackets::UnicodeBrackets;
+use unicode_xid::UnicodeXID;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+#[cfg(feature = "serde")]
+use serde::de::Error as DeError;
+
+/// Error returned by `validate_symbol_table`.
 #[derive(Debug, Clone, PartialEq)]
-pub struct InvalidSymbolTableError<'s> {
-    pub first: Vec<&'s str>,
-    pub second: Vec<&'s str>,
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum InvalidSymbolTableError {
+    /// The table contains the empty string.
+    EmptySymbol,
+    /// The symbol can never be lexed because it contains a character which the lexer treats as
+    /// the start of another kind of token.
+    Unreachable {
+        symbol: String,
+        c: char,
+        collision: Collision,
+    },
+    /// Two different sequences of symbols spell out the same text, so the text can't be lexed
+    /// unambiguously. `text` is the shortest such text.
+    Ambiguous {
+        text: String,
+        first: Vec<String>,
+        second: Vec<String>,
+    },
 }
 
-/// Check that a symbol table can be used to parse unambiguously.
-pub fn validate_symbol_table<'s>(symbols: &[&'s str]) -> Result<(), InvalidSymbolTableError<'s>> {
-    fn recurse<'s>(symbols: &[&'s str],
-                   complete_list: &mut Vec<&'s str>,
-                   postfix_list: &mut Vec<&'s str>,
-                   postfix: &'s str) -> Result<(), InvalidSymbolTableError<'s>>
-    {
-        for symbol in symbols.iter() {
-            if postfix.is_prefix_of(symbol) {
-                complete_list.push(*symbol);
-                let new_postfix = &symbol[postfix.len()..];
-                if new_postfix == "" {
-                    return Err(InvalidSymbolTableError {
-                        first: complete_list.clone(),
-                        second: postfix_list.clone(),
-                    })
+/// The kind of token that a character in an unreachable symbol collides with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Collision {
+    Whitespace,
+    Ident,
+    Bracket,
+    Quote,
+    Number,
+}
+
+#[cfg(feature = "std")]
+impl error::Error for InvalidSymbolTableError {}
+
+impl fmt::Display for InvalidSymbolTableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write_symbols(f: &mut fmt::Formatter, symbols: &[String]) -> fmt::Result {
+            for (i, symbol) in symbols.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
                 }
-                try!(recurse(symbols, postfix_list, complete_list, new_postfix));
-                let _ = complete_list.pop();
+                write!(f, "`{}`", symbol)?;
             }
+            Ok(())
+        }
+
+        match *self {
+            InvalidSymbolTableError::EmptySymbol => {
+                write!(f, "the empty string is not a valid symbol")
+            },
+            InvalidSymbolTableError::Unreachable { ref symbol, c, collision } => {
+                let kind = match collision {
+                    Collision::Whitespace => "whitespace",
+                    Collision::Ident => "an identifier",
+                    Collision::Bracket => "a bracket",
+                    Collision::Quote => "a string literal",
+                    Collision::Number => "a numeric literal",
+                };
+                write!(f, "the symbol `{}` can never be lexed because {:?} is lexed as {}",
+                       symbol, c, kind)
+            },
+            InvalidSymbolTableError::Ambiguous { ref text, ref first, ref second } => {
+                write!(f, "the symbol sequences ")?;
+                write_symbols(f, &first[..])?;
+                write!(f, " and ")?;
+                write_symbols(f, &second[..])?;
+                write!(f, " both spell out `{}`", text)
+            },
         }
-        Ok(())
+    }
+}
+
+/// Check whether the lexer could ever produce `symbol`, given the default lexer configuration.
+/// The first character of a symbol can't start any other kind of token and no character can
+/// end a symbol early.
+fn check_reachable(symbol: &str) -> Result<(), InvalidSymbolTableError> {
+    for (i, c) in symbol.chars().enumerate() {
+        let collision = if c.is_whitespace() {
+            Some(Collision::Whitespace)
+        }
+        else if c.is_xid_start() {
+            Some(Collision::Ident)
+        }
+        else if c.is_open_bracket() || c.is_close_bracket() {
+            Some(Collision::Bracket)
+        }
+        else if c == '\'' || c == '"' {
+            Some(Collision::Quote)
+        }
+        else if i == 0 && c.is_ascii_digit() {
+            Some(Collision::Number)
+        }
+        else {
+            None
+        };
+        if let Some(collision) = collision {
+            return Err(InvalidSymbolTableError::Unreachable {
+                symbol: String::from(symbol),
+                c,
+                collision,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Find the shortest text which can be split into symbols in two different ways, returning the
+/// text and both splittings.
+///
+/// This is a variant of the Sardinas-Patterson algorithm. We grow two sequences of symbols which
+/// spell out the same text, except that the one which is ahead has a "dangling" suffix which the
+/// other sequence has yet to match. A dangling suffix is always a suffix of some symbol so there
+/// are only polynomially many of them. Searching them in order of text length (ie. Dijkstra's
+/// algorithm) finds the shortest text where the dangling suffix disappears.
+fn find_ambiguity<'s>(symbols: &[&'s str]) -> Option<(String, Vec<&'s str>, Vec<&'s str>)> {
+    /// A symbol appended to one of the two sequences, along with the resulting search state.
+    struct Step<'s> {
+        parent: Option<usize>,
+        symbol: &'s str,
+        first: bool,
+        dangling: &'s str,
+        ahead_first: bool,
     }
 
-    for (i0, a) in symbols.iter().enumerate() {
-        for (i1, b) in symbols.iter().enumerate() {
-            if i0 == i1 {
+    let mut steps: Vec<Step<'s>> = Vec::new();
+    let mut queue = BinaryHeap::new();
+    for (i, a) in symbols.iter().enumerate() {
+        for (j, b) in symbols.iter().enumerate() {
+            if i == j || !b.starts_with(a) || (a.len() == b.len() && i > j) {
                 continue;
             }
-            if a.is_prefix_of(b) {
-                let mut complete_list = vec![*a];
-                let mut postfix_list = vec![*b];
-                let postfix = &b[a.len()..];
-                try!(recurse(symbols,
-                             &mut complete_list,
-                             &mut postfix_list,
-                             postfix));
+            steps.push(Step {
+                parent: None,
+                symbol: b,
+                first: true,
+                dangling: "",
+                ahead_first: true,
+            });
+            steps.push(Step {
+                parent: Some(steps.len() - 1),
+                symbol: a,
+                first: false,
+                dangling: &b[a.len()..],
+                ahead_first: true,
+            });
+            queue.push(Reverse((b.len(), steps.len() - 1)));
+        }
+    }
+
+    let mut settled = BTreeSet::new();
+    while let Some(Reverse((len, index))) = queue.pop() {
+        let dangling = steps[index].dangling;
+        let ahead_first = steps[index].ahead_first;
+        if dangling.is_empty() {
+            let mut first = Vec::new();
+            let mut second = Vec::new();
+            let mut step = Some(index);
+            while let Some(index) = step {
+                match steps[index].first {
+                    true => first.push(steps[index].symbol),
+                    false => second.push(steps[index].symbol),
+                }
+                step = steps[index].parent;
             }
+            first.reverse();
+            second.reverse();
+            return Some((first.concat(), first, second));
+        }
+        if !settled.insert(dangling) {
+            continue;
+        }
+
+        // Extend the sequence which is behind.
+        for symbol in symbols {
+            let (new_dangling, new_ahead_first, new_len) = if let Some(rest) = dangling.strip_prefix(symbol) {
+                (rest, ahead_first, len)
+            }
+            else if let Some(rest) = symbol.strip_prefix(dangling) {
+                (rest, !ahead_first, len + rest.len())
+            }
+            else {
+                continue;
+            };
+            if settled.contains(new_dangling) {
+                continue;
+            }
+            steps.push(Step {
+                parent: Some(index),
+                symbol,
+                first: !ahead_first,
+                dangling: new_dangling,
+                ahead_first: new_ahead_first,
+            });
+            queue.push(Reverse((new_len, steps.len() - 1)));
         }
     }
-    Ok(())
+    None
+}
+
+fn check_nonempty(symbols: &[&str]) -> Result<(), InvalidSymbolTableError> {
+    match symbols.iter().any(|symbol| symbol.is_empty()) {
+        true => Err(InvalidSymbolTableError::EmptySymbol),
+        false => Ok(()),
+    }
+}
+
+/// Check that no symbol is empty and that no text can be split into symbols in more than one
+/// way.
+fn check_decodable(symbols: &[&str]) -> Result<(), InvalidSymbolTableError> {
+    check_nonempty(symbols)?;
+    match find_ambiguity(symbols) {
+        None => Ok(()),
+        Some((text, first, second)) => Err(InvalidSymbolTableError::Ambiguous {
+            text,
+            first: first.into_iter().map(String::from).collect(),
+            second: second.into_iter().map(String::from).collect(),
+        }),
+    }
+}
+
+/// Check that a symbol table can be used by `lex`. This checks that no symbol can be mistaken
+/// for any other kind of token by the lexer in its default configuration and that no text can
+/// be split into symbols in more than one way. Takes polynomial time in the total length of the
+/// symbols.
+pub fn validate_symbol_table(symbols: &[&str]) -> Result<(), InvalidSymbolTableError> {
+    for symbol in symbols {
+        if !symbol.is_empty() {
+            check_reachable(symbol)?;
+        }
+    }
+    check_decodable(symbols)
+}
+
+/// A possible problem with a symbol table used in longest-match mode, found by
+/// `analyze_symbol_table`. Unlike an `InvalidSymbolTableError` these don't stop the table from
+/// being used, but they usually point to a mistake.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SymbolTableWarning {
+    /// The symbol appears more than once in the table. Only the first occurrence is ever lexed.
+    Duplicate {
+        symbol: String,
+    },
+    /// The symbol can never be lexed because it contains a character which the lexer treats as
+    /// the start of another kind of token.
+    Unreachable {
+        symbol: String,
+        c: char,
+        collision: Collision,
+    },
+    /// Writing `first` directly followed by `second` doesn't lex as those two symbols, because
+    /// the longer symbol `longest` is matched instead. The two symbols need to be separated by
+    /// whitespace to be lexed separately.
+    Adjacent {
+        first: String,
+        second: String,
+        longest: String,
+    },
+}
+
+impl fmt::Display for SymbolTableWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SymbolTableWarning::Duplicate { ref symbol } => {
+                write!(f, "the symbol `{}` appears more than once", symbol)
+            },
+            SymbolTableWarning::Unreachable { ref symbol, c, collision } => {
+                let err = InvalidSymbolTableError::Unreachable {
+                    symbol: symbol.clone(),
+                    c,
+                    collision,
+                };
+                write!(f, "{}", err)
+            },
+            SymbolTableWarning::Adjacent { ref first, ref second, ref longest } => {
+                write!(f, "`{}` followed by `{}` is lexed starting with `{}`", first, second, longest)
+            },
+        }
+    }
+}
+
+/// Look for likely mistakes in a symbol table meant for longest-match mode. This is the
+/// counterpart to `validate_symbol_table`: longest-match tables don't need to be uniquely
+/// decodable, so instead this reports duplicated symbols, symbols which the lexer in its default
+/// configuration could never produce, and pairs of symbols which get lexed as some other symbol
+/// when written next to each other. Empty symbols are skipped.
+pub fn analyze_symbol_table(symbols: &[&str]) -> Vec<SymbolTableWarning> {
+    let mut warnings = Vec::new();
+    let mut unique: Vec<&str> = Vec::new();
+    for symbol in symbols {
+        if symbol.is_empty() {
+            continue;
+        }
+        match unique.contains(symbol) {
+            true => warnings.push(SymbolTableWarning::Duplicate {
+                symbol: String::from(*symbol),
+            }),
+            false => unique.push(symbol),
+        }
+    }
+    let symbols = unique;
+    for symbol in &symbols {
+        if let Err(InvalidSymbolTableError::Unreachable { symbol, c, collision }) = check_reachable(symbol) {
+            warnings.push(SymbolTableWarning::Unreachable {
+                symbol,
+                c,
+                collision,
+            });
+        }
+    }
+
+    let table = SymbolTable::compile(&symbols[..]).longest_match_mode();
+    for first in &symbols {
+        for second in &symbols {
+            let text = format!("{}{}", first, second);
+            if let Some(index) = table.find(&text, |_| false) {
+                let longest = table.symbols()[index];
+                if longest.len() > first.len() {
+                    warnings.push(SymbolTableWarning::Adjacent {
+                        first: String::from(*first),
+                        second: String::from(*second),
+                        longest: String::from(longest),
+                    });
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// A node in a `SymbolTable`'s trie.
+#[derive(Debug, Clone)]
+struct Node {
+    /// The child nodes, sorted by the character leading to them.
+    children: Vec<(char, usize)>,
+    /// The index of the symbol which ends at this node, if any.
+    symbol: Option<usize>,
+    /// The number of symbols which start with the prefix this node represents.
+    count: usize,
+}
+
+/// A symbol table compiled into a trie, so that symbols can be matched in time linear in their
+/// length. Build one once and reuse it for every file being lexed.
+///
+/// Each symbol is registered with a value of type `S` which is attached to the `Symbol` tokens
+/// produced for it, so that parsers can match on an enum of operators rather than comparing
+/// strings. Tables built with `SymbolTable::new` use each symbol's index in the table as its
+/// value.
+#[derive(Debug, Clone)]
+pub struct SymbolTable<'s, S = usize> {
+    symbols: Vec<&'s str>,
+    values: Vec<S>,
+    nodes: Vec<Node>,
+    mode: MatchMode,
+}
+
+/// How a `SymbolTable` decides which symbol to match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MatchMode {
+    /// The table is uniquely decodable and a symbol is matched as soon as the text so far can
+    /// only be that symbol. This is the mode used by `SymbolTable::new`.
+    Unique,
+    /// The longest symbol which matches is taken, as in most programming languages. This allows
+    /// tables such as `<`, `<<`, `<=` and `<<=` which aren't uniquely decodable, at the cost of
+    /// `<` `<` written together being lexed as `<<`.
+    Longest,
+}
+
+impl<'s> SymbolTable<'s> {
+    /// Check that a symbol table is unambiguous and compile it for matching. Each symbol's value
+    /// is its index in `symbols`.
+    ///
+    /// Whether a symbol collides with other kinds of token depends on the `LexerConfig` the table
+    /// is used with, so that isn't checked here. Use `validate_symbol_table` to check a table
+    /// against the default configuration.
+    pub fn new(symbols: &[&'s str]) -> Result<SymbolTable<'s>, InvalidSymbolTableError> {
+        check_decodable(symbols)?;
+        Ok(SymbolTable::compile(symbols))
+    }
+
+    /// Compile a symbol table which matches the longest symbol it can. The table doesn't need to
+    /// be uniquely decodable, only the empty symbol is rejected. Use `analyze_symbol_table` to
+    /// look for likely mistakes in the table. Each symbol's value is its index in `symbols`.
+    pub fn longest_match(symbols: &[&'s str]) -> Result<SymbolTable<'s>, InvalidSymbolTableError> {
+        check_nonempty(symbols)?;
+        Ok(SymbolTable::compile(symbols).longest_match_mode())
+    }
+
+    /// Compile a symbol table without validating it.
+    pub(crate) fn compile(symbols: &[&'s str]) -> SymbolTable<'s> {
+        SymbolTable::compile_with_values(symbols.to_vec(), (0..symbols.len()).collect())
+    }
+}
+
+impl<'s, S> SymbolTable<'s, S> {
+    /// Check that a symbol table where each symbol is registered with a value is unambiguous, and
+    /// compile it for matching. See `SymbolTable::new`.
+    pub fn with_values(entries: &[(&'s str, S)]) -> Result<SymbolTable<'s, S>, InvalidSymbolTableError>
+        where S: Clone
+    {
+        let symbols: Vec<&'s str> = entries.iter().map(|&(symbol, _)| symbol).collect();
+        check_decodable(&symbols[..])?;
+        let values = entries.iter().map(|(_, value)| value.clone()).collect();
+        Ok(SymbolTable::compile_with_values(symbols, values))
+    }
+
+    /// Compile a symbol table where each symbol is registered with a value, matching the longest
+    /// symbol it can. See `SymbolTable::longest_match`.
+    pub fn longest_match_with_values(entries: &[(&'s str, S)])
+        -> Result<SymbolTable<'s, S>, InvalidSymbolTableError>
+        where S: Clone
+    {
+        let symbols: Vec<&'s str> = entries.iter().map(|&(symbol, _)| symbol).collect();
+        check_nonempty(&symbols[..])?;
+        let values = entries.iter().map(|(_, value)| value.clone()).collect();
+        Ok(SymbolTable::compile_with_values(symbols, values).longest_match_mode())
+    }
+
+    fn longest_match_mode(mut self) -> SymbolTable<'s, S> {
+        self.mode = MatchMode::Longest;
+        self
+    }
+
+    fn compile_with_values(symbols: Vec<&'s str>, values: Vec<S>) -> SymbolTable<'s, S> {
+        let mut nodes = vec![Node {
+            children: Vec::new(),
+            symbol: None,
+            count: 0,
+        }];
+        for (index, symbol) in symbols.iter().enumerate() {
+            let mut node = 0;
+            nodes[node].count += 1;
+            for c in symbol.chars() {
+                node = match nodes[node].children.binary_search_by_key(&c, |&(c, _)| c) {
+                    Ok(i) => nodes[node].children[i].1,
+                    Err(i) => {
+                        let child = nodes.len();
+                        nodes[node].children.insert(i, (c, child));
+                        nodes.push(Node {
+                            children: Vec::new(),
+                            symbol: None,
+                            count: 0,
+                        });
+                        child
+                    },
+                };
+                nodes[node].count += 1;
+            }
+            if nodes[node].symbol.is_none() {
+                nodes[node].symbol = Some(index);
+            }
+        }
+        SymbolTable {
+            symbols,
+            values,
+            nodes,
+            mode: MatchMode::Unique,
+        }
+    }
+
+    /// The symbols in the table.
+    pub fn symbols(&self) -> &[&'s str] {
+        &self.symbols[..]
+    }
+
+    /// How the table decides which symbol to match.
+    pub fn mode(&self) -> MatchMode {
+        self.mode
+    }
+
+    /// The values the symbols were registered with, in the same order as `symbols`.
+    pub fn values(&self) -> &[S] {
+        &self.values[..]
+    }
+
+    /// Get the value a symbol was registered with.
+    pub fn get(&self, symbol: &str) -> Option<&S> {
+        let mut node = 0;
+        for c in symbol.chars() {
+            node = match self.nodes[node].children.binary_search_by_key(&c, |&(c, _)| c) {
+                Ok(i) => self.nodes[node].children[i].1,
+                Err(_) => return None,
+            };
+        }
+        self.nodes[node].symbol.map(|index| &self.values[index])
+    }
+
+    /// Find the symbol at the start of `text`. The match is extended one character at a time.
+    /// In `Unique` mode it stops when the text so far is a symbol and no other symbol starts with
+    /// it, in `Longest` mode it carries on as far as possible and takes the last symbol it passed.
+    /// Characters for which `is_break` returns `true` can't appear after the first character of
+    /// a symbol. Returns the index of the symbol.
+    pub(crate) fn find<F>(&self, text: &str, is_break: F) -> Option<usize>
+        where F: Fn(char) -> bool
+    {
+        let mut node = 0;
+        let mut longest = None;
+        for (i, c) in text.chars().enumerate() {
+            if i > 0 && is_break(c) {
+                break;
+            }
+            node = match self.nodes[node].children.binary_search_by_key(&c, |&(c, _)| c) {
+                Ok(i) => self.nodes[node].children[i].1,
+                Err(_) => break,
+            };
+            match self.mode {
+                MatchMode::Unique => {
+                    if self.nodes[node].count == 1 {
+                        if let Some(index) = self.nodes[node].symbol {
+                            return Some(index);
+                        }
+                    }
+                },
+                MatchMode::Longest => {
+                    if self.nodes[node].symbol.is_some() {
+                        longest = self.nodes[node].symbol;
+                    }
+                },
+            }
+        }
+        longest
+    }
+}
+
+/// The serialized form of a `SymbolTable`. The trie isn't serialized, it's rebuilt when the table
+/// is deserialized.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SymbolTableRepr<'s, S> {
+    #[serde(borrow)]
+    symbols: Vec<&'s str>,
+    values: Vec<S>,
+    mode: MatchMode,
+}
+
+/// Serialized as `{"symbols": [...], "values": [...], "mode": "Unique"}`.
+#[cfg(feature = "serde")]
+impl<'s, S: Serialize> Serialize for SymbolTable<'s, S> {
+    fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+        #[derive(Serialize)]
+        struct Repr<'a, 's: 'a, S: 'a> {
+            symbols: &'a [&'s str],
+            values: &'a [S],
+            mode: MatchMode,
+        }
+
+        let repr = Repr {
+            symbols: &self.symbols[..],
+            values: &self.values[..],
+            mode: self.mode,
+        };
+        repr.serialize(serializer)
+    }
+}
+
+/// Deserializing checks the table the same way as `SymbolTable::new` or
+/// `SymbolTable::longest_match`, depending on its mode.
+#[cfg(feature = "serde")]
+impl<'de: 's, 's, S: Deserialize<'de>> Deserialize<'de> for SymbolTable<'s, S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SymbolTable<'s, S>, D::Error> {
+        let repr = SymbolTableRepr::deserialize(deserializer)?;
+        if repr.symbols.len() != repr.values.len() {
+            return Err(D::Error::custom("symbol table has a different number of symbols and values"));
+        }
+        let check = match repr.mode {
+            MatchMode::Unique => check_decodable(&repr.symbols[..]),
+            MatchMode::Longest => check_nonempty(&repr.symbols[..]),
+        };
+        if let Err(e) = check {
+            return Err(D::Error::custom(e));
+        }
+        let mut table = SymbolTable::compile_with_values(repr.symbols, repr.values);
+        table.mode = repr.mode;
+        Ok(table)
+    }
+}
+
+impl<'s, S> Default for SymbolTable<'s, S> {
+    /// An empty symbol table.
+    fn default() -> SymbolTable<'s, S> {
+        SymbolTable::compile_with_values(Vec::new(), Vec::new())
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std;
+
+    #[test]
+    fn test_find() {
+        let table = SymbolTable::new(&["+", "->", "-->", "=="]).unwrap();
+        let find = |text| table.find(text, char::is_whitespace).map(|i| table.symbols()[i]);
+        assert_eq!(find("+1"), Some("+"));
+        assert_eq!(find("->x"), Some("->"));
+        assert_eq!(find("-->"), Some("-->"));
+        assert_eq!(find("-- >"), None);
+        assert_eq!(find("="), None);
+        assert_eq!(find("*"), None);
+    }
+
+    #[test]
+    fn test_find_longest() {
+        let table = SymbolTable::longest_match(&["<", "<<", "<=", "<<=", "-", "-->"]).unwrap();
+        let find = |text| table.find(text, char::is_whitespace).map(|i| table.symbols()[i]);
+        assert_eq!(find("<"), Some("<"));
+        assert_eq!(find("<<"), Some("<<"));
+        assert_eq!(find("<<<"), Some("<<"));
+        assert_eq!(find("<<="), Some("<<="));
+        assert_eq!(find("< ="), Some("<"));
+        assert_eq!(find("--x"), Some("-"));
+        assert_eq!(find("-->"), Some("-->"));
+        assert_eq!(find("="), None);
+
+        assert_eq!(SymbolTable::longest_match(&["<", ""]).unwrap_err(), InvalidSymbolTableError::EmptySymbol);
+    }
+
+    #[test]
+    fn test_analyze_symbol_table() {
+        let warnings = analyze_symbol_table(&["<", "<<", "=", "<=", "a<", "="]);
+        assert_eq!(warnings, vec![
+            SymbolTableWarning::Duplicate {
+                symbol: String::from("="),
+            },
+            SymbolTableWarning::Unreachable {
+                symbol: String::from("a<"),
+                c: 'a',
+                collision: Collision::Ident,
+            },
+            SymbolTableWarning::Adjacent {
+                first: String::from("<"),
+                second: String::from("<"),
+                longest: String::from("<<"),
+            },
+            SymbolTableWarning::Adjacent {
+                first: String::from("<"),
+                second: String::from("<<"),
+                longest: String::from("<<"),
+            },
+            SymbolTableWarning::Adjacent {
+                first: String::from("<"),
+                second: String::from("="),
+                longest: String::from("<="),
+            },
+            SymbolTableWarning::Adjacent {
+                first: String::from("<"),
+                second: String::from("<="),
+                longest: String::from("<<"),
+            },
+        ]);
+        assert_eq!(analyze_symbol_table(&["+", "-"]), vec![]);
+    }
+
+    #[test]
+    fn test_values() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        enum Op {
+            Add,
+            Arrow,
+        }
+
+        let table = SymbolTable::with_values(&[("+", Op::Add), ("->", Op::Arrow)]).unwrap();
+        assert_eq!(table.get("->"), Some(&Op::Arrow));
+        assert_eq!(table.get("-"), None);
+        assert_eq!(table.values(), [Op::Add, Op::Arrow]);
+
+        let table = SymbolTable::new(&["+", "->"]).unwrap();
+        assert_eq!(table.get("->"), Some(&1));
+    }
 
     #[test]
     fn test_invalid_symbol_table() {
@@ -66,13 +696,66 @@ mod test {
             "$%^",
         ];
         let err = validate_symbol_table(&symbols[..]).unwrap_err();
-        let mut first = err.first;
-        let mut second = err.second;
+        let (text, mut first, mut second) = match err {
+            InvalidSymbolTableError::Ambiguous { text, first, second } => (text, first, second),
+            _ => panic!(),
+        };
         if second.len() < first.len() {
-            std::mem::swap(&mut first, &mut second);
+            core::mem::swap(&mut first, &mut second);
         }
+        assert_eq!(text, "!@#$%^");
         assert_eq!(&first[..], ["!@#", "$%^"]);
         assert_eq!(&second[..], ["!@", "#$", "%^"]);
     }
-}
 
+    #[test]
+    fn test_shortest_ambiguity() {
+        let symbols = [
+            "<",
+            "<<<<<<",
+            "<<",
+        ];
+        let err = validate_symbol_table(&symbols[..]).unwrap_err();
+        assert_eq!(err, InvalidSymbolTableError::Ambiguous {
+            text: String::from("<<"),
+            first: vec![String::from("<<")],
+            second: vec![String::from("<"), String::from("<")],
+        });
+
+        let err = validate_symbol_table(&["+", "+"]).unwrap_err();
+        assert_eq!(err, InvalidSymbolTableError::Ambiguous {
+            text: String::from("+"),
+            first: vec![String::from("+")],
+            second: vec![String::from("+")],
+        });
+
+        // Exponentially many partial splittings, but no ambiguity.
+        let symbols: Vec<String> = (1..40).map(|n| {
+            let mut symbol: String = core::iter::repeat_n('+', n).collect();
+            symbol.push('-');
+            symbol
+        }).collect();
+        let symbols: Vec<&str> = symbols.iter().map(|s| &s[..]).collect();
+        assert_eq!(validate_symbol_table(&symbols[..]), Ok(()));
+    }
+
+    #[test]
+    fn test_unreachable_symbols() {
+        assert_eq!(validate_symbol_table(&["+", ""]), Err(InvalidSymbolTableError::EmptySymbol));
+        assert_eq!(validate_symbol_table(&["-x"]), Err(InvalidSymbolTableError::Unreachable {
+            symbol: String::from("-x"),
+            c: 'x',
+            collision: Collision::Ident,
+        }));
+        assert_eq!(validate_symbol_table(&["1+"]), Err(InvalidSymbolTableError::Unreachable {
+            symbol: String::from("1+"),
+            c: '1',
+            collision: Collision::Number,
+        }));
+        assert_eq!(validate_symbol_table(&["+1", "=>", "(+"]), Err(InvalidSymbolTableError::Unreachable {
+            symbol: String::from("(+"),
+            c: '(',
+            collision: Collision::Bracket,
+        }));
+    }
+}
@@ -1,46 +1,222 @@
-use tokens::TokensBuf;
-use span::TextPos;
+use crate::tokens::TokensBuf;
+use crate::span::{TextPos, Span};
 
-use std::borrow::Cow;
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::slice;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 /// An element in a token tree.
 #[derive(Debug, Clone, PartialEq)]
-pub enum TokenKind<'s> {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TokenKind<'s, S = usize> {
     /// A string of identifier characters.
     Ident(&'s str),
     
     /// A string of whitespace characters.
     Whitespace(&'s str),
 
-    /// A valid symbol from the symbol table used when parsing.
-    Symbol(&'s str),
+    /// A valid symbol from the symbol table used when parsing, along with the value it was
+    /// registered with in the `SymbolTable`.
+    Symbol(&'s str, S),
     
-    /// A bracket sequence of tokens.
-    Bracket(char, TokensBuf<'s>),
+    /// A bracket sequence of tokens: the opening bracket, the tokens inside it and the closing
+    /// bracket. The closing bracket is `None` if it was missing and the token was produced while
+    /// recovering from errors, and may not match the opening bracket in that case either.
+    Bracket(char, #[cfg_attr(feature = "serde", serde(borrow))] TokensBuf<'s, S>, Option<char>),
+
+    /// A string literal.
+    String(#[cfg_attr(feature = "serde", serde(borrow))] StringLit<'s>),
+
+    /// A numeric literal.
+    Number(#[cfg_attr(feature = "serde", serde(borrow))] NumberLit<'s>),
+
+    /// A comment, including its delimiters.
+    Comment(&'s str),
+
+    /// Text which couldn't be lexed. Only produced when recovering from errors.
+    Error(&'s str),
+}
 
-    /// An unescaped string literal
-    String(Cow<'s, str>),
+/// A string literal.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StringLit<'s> {
+    /// The quote character which delimits the string.
+    pub quote: char,
+    /// The number of `#` characters fencing a raw string such as `r#"..."#`, or `None` if this
+    /// isn't a raw string.
+    pub hashes: Option<usize>,
+    /// The source text between the quotes, with escapes left unprocessed.
+    pub raw: &'s str,
+    /// The unescaped contents of the string.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub value: Cow<'s, str>,
+}
+
+/// A numeric literal, split into its parts. Each part is a slice of the source text and may
+/// contain `_` separators.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NumberLit<'s> {
+    /// The whole literal as it appears in the source.
+    pub raw: &'s str,
+    /// The radix of the literal. One of 2, 8, 10 or 16.
+    pub radix: u32,
+    /// The digits of the integer part, not including any radix prefix.
+    pub integer: &'s str,
+    /// The digits after the decimal point, if there is one.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub fraction: Option<&'s str>,
+    /// The digits of the exponent, including its sign if it has one.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub exponent: Option<&'s str>,
+    /// A suffix such as `u32` or `f64`.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub suffix: Option<&'s str>,
 }
 
 /// A token with a position.
 #[derive(Debug, Clone, PartialEq)]
-pub struct Token<'s> {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Token<'s, S = usize> {
     /// The position of the start of the token.
     pub start: TextPos,
 
+    /// The position of the end of the token.
+    pub end: TextPos,
+
     /// The kind of token.
-    pub kind: TokenKind<'s>,
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub kind: TokenKind<'s, S>,
 }
 
-impl<'s> Token<'s> {
+impl<'s, S> Token<'s, S> {
+    /// Get the span of the token.
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.start,
+            end: self.end,
+        }
+    }
+
+    /// If this is a bracket token, get the span of its opening bracket.
+    pub fn open_span(&self) -> Option<Span> {
+        match self.kind {
+            TokenKind::Bracket(_, ref tokens, _) => Some(Span {
+                start: self.start,
+                end: tokens.start,
+            }),
+            _ => None,
+        }
+    }
+
+    /// If this is a bracket token, get the span of its closing bracket. This is empty if the
+    /// closing bracket was missing and the token was produced while recovering from errors.
+    pub fn close_span(&self) -> Option<Span> {
+        match self.kind {
+            TokenKind::Bracket(_, ref tokens, _) => Some(Span {
+                start: tokens.end,
+                end: self.end,
+            }),
+            _ => None,
+        }
+    }
+
     /// Check whether a token is a whitespace token.
     pub fn is_whitespace(&self) -> bool {
-        if let TokenKind::Whitespace(_) = self.kind {
-            true
+        matches!(self.kind, TokenKind::Whitespace(_))
+    }
+
+    /// Check whether a token is a comment token.
+    pub fn is_comment(&self) -> bool {
+        matches!(self.kind, TokenKind::Comment(_))
+    }
+
+    /// Print the token back out exactly as it appeared in the source.
+    pub fn to_source(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<'s> fmt::Display for StringLit<'s> {
+    /// Prints the literal as it appeared in the source, including its quotes.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.hashes {
+            Some(hashes) => {
+                let fence: String = core::iter::repeat_n('#', hashes).collect();
+                write!(f, "r{}{}{}{}{}", fence, self.quote, self.raw, self.quote, fence)
+            },
+            None => write!(f, "{}{}{}", self.quote, self.raw, self.quote),
         }
-        else {
-            false
+    }
+}
+
+impl<'s> fmt::Display for NumberLit<'s> {
+    /// Prints the literal as it appeared in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.raw)
+    }
+}
+
+impl<'s, S> fmt::Display for TokenKind<'s, S> {
+    /// Prints the token as it appeared in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TokenKind::Ident(s) |
+            TokenKind::Whitespace(s) |
+            TokenKind::Symbol(s, _) |
+            TokenKind::Comment(s) |
+            TokenKind::Error(s) => f.write_str(s),
+            TokenKind::String(ref string) => write!(f, "{}", string),
+            TokenKind::Number(ref number) => write!(f, "{}", number),
+            TokenKind::Bracket(open, ref tokens, close) => {
+                write!(f, "{}", open)?;
+                write_tokens(f, &tokens.tokens[..])?;
+                match close {
+                    Some(close) => write!(f, "{}", close),
+                    None => Ok(()),
+                }
+            },
         }
     }
 }
 
+impl<'s, S> fmt::Display for Token<'s, S> {
+    /// Prints the token as it appeared in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
+
+/// Print a list of token trees as they appeared in the source. Brackets are handled with an
+/// explicit stack rather than recursion so that deeply nested trees can't overflow the stack.
+pub(crate) fn write_tokens<S>(f: &mut fmt::Formatter, tokens: &[Token<S>]) -> fmt::Result {
+    let mut stack: Vec<(slice::Iter<Token<S>>, Option<char>)> = vec![(tokens.iter(), None)];
+    loop {
+        let next = match stack.last_mut() {
+            Some(&mut (ref mut iter, _)) => iter.next(),
+            None => return Ok(()),
+        };
+        match next {
+            Some(token) => {
+                match token.kind {
+                    TokenKind::Bracket(open, ref inner, close) => {
+                        write!(f, "{}", open)?;
+                        stack.push((inner.tokens.iter(), close));
+                    },
+                    ref kind => write!(f, "{}", kind)?,
+                }
+            },
+            None => {
+                if let Some((_, Some(close))) = stack.pop() {
+                    write!(f, "{}", close)?;
+                }
+            },
+        }
+    }
+}
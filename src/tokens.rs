@@ -1,64 +1,96 @@
-use span::{TextPos, Span};
-use token::Token;
+use crate::span::{TextPos, Span};
+use crate::token::{Token, write_tokens};
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct TokensBuf<'s> {
-    pub tokens: Vec<Token<'s>>,
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TokensBuf<'s, S = usize> {
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub tokens: Vec<Token<'s, S>>,
+    /// The start of the tokens. For the contents of a bracket this is the position after the
+    /// opening bracket.
+    pub start: TextPos,
+    /// The end of the tokens. For the contents of a bracket this is the position of the closing
+    /// bracket.
     pub end: TextPos,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct TokensRef<'t, 's: 't> {
-    pub tokens: &'t [Token<'s>],
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TokensRef<'t, 's: 't, S: 't = usize> {
+    pub tokens: &'t [Token<'s, S>],
+    pub start: TextPos,
     pub end: TextPos,
 }
 
-impl<'s> TokensBuf<'s> {
+impl<'t, 's: 't, S: 't> Clone for TokensRef<'t, 's, S> {
+    fn clone(&self) -> TokensRef<'t, 's, S> {
+        *self
+    }
+}
+
+impl<'t, 's: 't, S: 't> Copy for TokensRef<'t, 's, S> {}
+
+impl<'s, S> TokensBuf<'s, S> {
     /// Produce a `TokensRef` from this `TokenBuf`
-    pub fn borrow<'t>(&'t self) -> TokensRef<'s, 't> {
+    pub fn borrow<'t>(&'t self) -> TokensRef<'t, 's, S> {
         TokensRef {
             tokens: &self.tokens[..],
+            start: self.start,
             end: self.end,
         }
     }
+
+    /// Print the tokens back out exactly as they appeared in the source. For the tokens returned
+    /// by `lex` or `lex_recovering` this gives back the source that was lexed.
+    pub fn to_source(&self) -> String {
+        self.to_string()
+    }
 }
 
-impl<'t, 's: 't> TokensRef<'t, 's> {
+impl<'t, 's: 't, S: 't> TokensRef<'t, 's, S> {
     /// Slice a range of tokens between `start_index` (inclusive) and `end_index` (exclusive).
-    pub fn range(&self, start_index: usize, end_index: usize) -> TokensRef<'t, 's> {
+    pub fn range(&self, start_index: usize, end_index: usize) -> TokensRef<'t, 's, S> {
         TokensRef {
             tokens: &self.tokens[start_index..end_index],
-            end: match self.tokens.get(end_index + 1) {
-                Some(token) => token.start,
-                None        => self.end,
-            },
+            start: self.pos_before(start_index),
+            end: self.pos_before(end_index),
         }
     }
 
     /// Slice a range of tokens between `start_index` and the end.
-    pub fn range_from(&self, start_index: usize) -> TokensRef<'t, 's> {
+    pub fn range_from(&self, start_index: usize) -> TokensRef<'t, 's, S> {
         TokensRef {
             tokens: &self.tokens[start_index..],
+            start: self.pos_before(start_index),
             end: self.end,
         }
     }
 
     /// Split around the token at `index` returning all the tokens before it and all the tokens
     /// after it.
-    pub fn split_around(&self, index: usize) -> (TokensRef<'t, 's>, TokensRef<'t, 's>) {
+    pub fn split_around(&self, index: usize) -> (TokensRef<'t, 's, S>, TokensRef<'t, 's, S>) {
         let l = TokensRef {
             tokens: &self.tokens[..index],
+            start: self.start,
             end: self.tokens[index].start,
         };
         let r = TokensRef {
             tokens: &self.tokens[(index + 1)..],
+            start: self.tokens[index].end,
             end: self.end,
         };
         (l, r)
     }
 
     /// Trim whitespace tokens from both sides.
-    pub fn trim_whitespace(&self) -> TokensRef<'t, 's> {
+    pub fn trim_whitespace(&self) -> TokensRef<'t, 's, S> {
         let mut start_index = None;
         for (index, token) in self.tokens.iter().enumerate() {
             if !token.is_whitespace() {
@@ -70,6 +102,7 @@ impl<'t, 's: 't> TokensRef<'t, 's> {
             Some(start_index) => start_index,
             None => return TokensRef {
                 tokens: &[],
+                start: self.end,
                 end: self.end,
             },
         };
@@ -82,26 +115,46 @@ impl<'t, 's: 't> TokensRef<'t, 's> {
             }
         }
         let end_index = end_index.unwrap() + 1;
-        let end_pos = match end_index == self.tokens.len() {
-            true  => self.end,
-            false => self.tokens[end_index].start,
-        };
         TokensRef {
             tokens: &self.tokens[start_index..end_index],
-            end: end_pos,
+            start: self.tokens[start_index].start,
+            end: self.tokens[end_index - 1].end,
         }
     }
 
     /// Get the span of this `TokensRef`
     pub fn span(&self) -> Span {
         Span {
-            start: match self.tokens.first() {
-                None => self.end,
-                Some(t) => t.start,
-            },
+            start: self.start,
             end: self.end,
         }
     }
+
+    /// Get the position just before the token at `index`, or the end position if `index` is the
+    /// number of tokens.
+    fn pos_before(&self, index: usize) -> TextPos {
+        match index {
+            0 => self.start,
+            _ => self.tokens[index - 1].end,
+        }
+    }
+
+    /// Print the tokens back out exactly as they appeared in the source.
+    pub fn to_source(&self) -> String {
+        self.to_string()
+    }
 }
 
+impl<'s, S> fmt::Display for TokensBuf<'s, S> {
+    /// Prints the tokens as they appeared in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tokens(f, &self.tokens[..])
+    }
+}
 
+impl<'t, 's: 't, S: 't> fmt::Display for TokensRef<'t, 's, S> {
+    /// Prints the tokens as they appeared in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tokens(f, self.tokens)
+    }
+}